@@ -0,0 +1,49 @@
+use std::{path::Path, sync::LazyLock};
+
+use symphonia::core::{
+    codecs::{CODEC_TYPE_NULL, CodecRegistry, DecoderOptions},
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+static CODECS: LazyLock<CodecRegistry> = LazyLock::new(|| {
+    let mut registry = CodecRegistry::new();
+    symphonia::default::register_enabled_codecs(&mut registry);
+    #[cfg(feature = "opus")]
+    registry.register_all::<super::opus::OpusDecoder>();
+    registry
+});
+
+/// Every decoder symphonia was built with, plus the ones this crate adds on top.
+/// Use this instead of `symphonia::default::get_codecs()`.
+pub fn get_codecs() -> &'static CodecRegistry {
+    &CODECS
+}
+
+/// Checks that `path` has an audio track one of our decoders accepts.
+pub fn check_decodable(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let file = Box::new(std::fs::File::open(path)?);
+    let mss = MediaSourceStream::new(file, Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension() {
+        hint.with_extension(ext.to_str().unwrap_or(""));
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let track = probed
+        .format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("No audio track")?;
+    get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+    Ok(())
+}
@@ -1,125 +1,562 @@
-use std::{collections::HashMap, path::PathBuf, process::exit, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    process::exit,
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use lofty::{
+    config::ParseOptions,
+    file::TaggedFileExt,
+    probe::Probe,
+    tag::{Accessor, ItemKey},
+};
 
 use crate::types::*;
+use actix_web::web;
+use serde::{Deserialize, Serialize};
 use symphonia::{
     core::{
-        formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, units::TimeStamp,
+        codecs::CODEC_TYPE_NULL,
+        errors::Error as SymphoniaError,
+        formats::{FormatOptions, FormatReader},
+        io::MediaSourceStream,
+        meta::{MetadataOptions, StandardTagKey},
+        units::TimeStamp,
     },
     default::get_probe,
 };
 use uuid::Uuid;
 use walkdir::WalkDir;
 
+use crate::helpers::{Library, check_decodable, fill_missing_loudness, replace_file};
 use crate::types::SongIndex;
 
-pub async fn generate_index(music_dir: &PathBuf) -> std::io::Result<()> {
-    // collect supported audio files
-    let mut songs: Vec<PathBuf> = Vec::new();
-    for entry in WalkDir::new(music_dir)
+/// Songs probed between merges into the index, so the lock is not taken for every file.
+const PROBE_BATCH: usize = 100;
+/// Songs merged between index saves during a rescan.
+const CHECKPOINT_EVERY: usize = 1000;
+
+/// Where the library lives.
+pub fn music_dir() -> PathBuf {
+    dirs::home_dir().unwrap().join("Music")
+}
+
+/// Whether `path` has one of the extensions we index. `.opus` only counts with the `opus`
+/// feature, on by default, without a decoder those files would fail every rescan.
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| {
+            let ext = ext.to_lowercase();
+            matches!(
+                ext.as_str(),
+                "mp3" | "flac" | "wav" | "ogg" | "m4a" | "aiff" | "aif"
+            ) || (cfg!(feature = "opus") && ext == "opus")
+        })
+}
+
+fn file_stamp(metadata: &std::fs::Metadata) -> Option<FileStamp> {
+    Some(FileStamp {
+        mtime: metadata.modified().ok()?,
+        size: metadata.len(),
+    })
+}
+
+/// An audio file found in the library.
+#[derive(Clone)]
+struct Found {
+    path: PathBuf,
+    stamp: Option<FileStamp>,
+    root: String,
+    file: PathBuf,
+    /// ID the song had when it was last probed, if it is in the index under this path.
+    previous: Option<Uuid>,
+}
+
+/// Every audio file under `target` that belongs to the library. Nothing if `target` is
+/// gone.
+fn walk(library: &Library, target: &Path) -> Vec<Found> {
+    WalkDir::new(target)
         .into_iter()
         .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-    {
-        let path = entry.path();
-        if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
-            match ext.to_lowercase().as_str() {
-                "mp3" | "flac" | "wav" | "ogg" | "m4a" => songs.push(path.to_path_buf()),
-                _ => {}
+        .filter(|e| e.file_type().is_file() && is_audio_file(e.path()))
+        .filter_map(|e| {
+            let (root, file) = library.locate(e.path())?;
+            Some(Found {
+                path: e.path().to_path_buf(),
+                stamp: e.metadata().ok().and_then(|m| file_stamp(&m)),
+                root: root.name(),
+                file: file.to_path_buf(),
+                previous: None,
+            })
+        })
+        .collect()
+}
+
+/// Hash of the audio packets of `track`, which leaves out the tags so retagging a file
+/// keeps its ID. A file that can't be read to the end is hashed up to where it broke off,
+/// it only fails if not a single packet came out.
+fn content_id(format: &mut dyn FormatReader, track: u32) -> Result<Uuid, String> {
+    let mut hasher = blake3::Hasher::new();
+    let mut hashed = 0;
+    loop {
+        match format.next_packet() {
+            Ok(packet) if packet.track_id() == track => {
+                hasher.update(packet.buf());
+                hashed += 1;
+            }
+            Ok(_) => {}
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break;
+            }
+            Err(e) if hashed > 0 => {
+                tracing::warn!("Hashing the first {hashed} packets only: {e}");
+                break;
             }
+            Err(e) => return Err(format!("read error: {e}")),
         }
     }
+    Ok(Uuid::new_v5(
+        &Uuid::NAMESPACE_OID,
+        hasher.finalize().as_bytes(),
+    ))
+}
 
-    tracing::info!("Found {} songs.", songs.len());
-
-    let mut index: SongIndex = HashMap::new();
-
-    for path in songs {
-        let file = match std::fs::File::open(&path) {
-            Ok(f) => f,
-            Err(e) => {
-                tracing::warn!("Skipping {:?}: open error: {}", path, e);
-                continue;
-            }
-        };
+/// Reads the tags and length of one file and hashes its audio.
+fn probe_song(found: Found) -> Result<SongMeta, String> {
+    let Found {
+        path,
+        stamp,
+        root,
+        file,
+        previous: _,
+    } = found;
+    check_decodable(&path).map_err(|e| format!("cannot decode: {e}"))?;
+    let source = std::fs::File::open(&path).map_err(|e| format!("open error: {e}"))?;
 
-        let mss = MediaSourceStream::new(Box::new(file), Default::default());
-        let mut probe = match get_probe().format(
+    let mss = MediaSourceStream::new(Box::new(source), Default::default());
+    let mut probe = get_probe()
+        .format(
             &Default::default(),
             mss,
             &FormatOptions::default(),
             &MetadataOptions::default(),
-        ) {
-            Ok(p) => p,
-            Err(e) => {
-                tracing::warn!("Skipping {:?}: probe error: {}", path, e);
-                continue;
+        )
+        .map_err(|e| format!("probe error: {e}"))?;
+
+    let mut format = probe.format;
+
+    let mut duration = Duration::ZERO;
+    let mut replaygain = ReplayGain::default();
+    let mut meta_opt = format.metadata();
+
+    if meta_opt.current().is_none()
+        && let Some(meta) = probe.metadata.get()
+    {
+        meta_opt = meta;
+    }
+    if let Some(rev) = meta_opt.current() {
+        for tag in rev.tags() {
+            let val = tag.value.to_string();
+            match tag.std_key {
+                Some(StandardTagKey::ReplayGainTrackGain) => {
+                    replaygain.track_gain = parse_replaygain(&val)
+                }
+                Some(StandardTagKey::ReplayGainTrackPeak) => {
+                    replaygain.track_peak = parse_replaygain(&val)
+                }
+                Some(StandardTagKey::ReplayGainAlbumGain) => {
+                    replaygain.album_gain = parse_replaygain(&val)
+                }
+                Some(StandardTagKey::ReplayGainAlbumPeak) => {
+                    replaygain.album_peak = parse_replaygain(&val)
+                }
+                _ => {}
             }
-        };
+        }
+    }
+
+    if let Some(track) = format.tracks().first()
+        && let (Some(tb), Some(n_frames)) =
+            (track.codec_params.time_base, track.codec_params.n_frames)
+    {
+        let ts: TimeStamp = n_frames as TimeStamp;
+        let time = tb.calc_time(ts); // has .seconds (u64) and .frac (f64)
+        duration =
+            Duration::from_secs(time.seconds) + Duration::from_millis((time.frac * 1000.) as u64)
+    }
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("no audio track")?
+        .id;
+    let id = content_id(format.as_mut(), track)?;
+    let (title, artists, tags) = read_tags(&path);
 
-        let mut format = probe.format;
+    Ok(SongMeta {
+        id,
+        title,
+        artists,
+        duration,
+        path,
+        root,
+        file,
+        tags,
+        replaygain,
+        stamp,
+    })
+}
 
-        // Defaults
-        let mut title = path
-            .file_stem()
+/// Title, artists and the rest of the tags, through lofty so every tag format maps onto the
+/// same keys. Untagged files get their file name as title.
+fn read_tags(path: &Path) -> (String, Vec<String>, Tags) {
+    let fallback_title = || {
+        path.file_stem()
             .and_then(|s| s.to_str())
             .unwrap_or("Unknown")
-            .to_string();
-        let mut artist = "Unknown".to_string();
-        let mut duration = Duration::ZERO;
-        let mut meta_opt = format.metadata();
-
-        if meta_opt.current().is_none() {
-            if let Some(meta) = probe.metadata.get() {
-                meta_opt = meta;
-            }
+            .to_string()
+    };
+    let tagged = Probe::open(path)
+        .and_then(|probe| {
+            probe
+                .options(ParseOptions::new().read_properties(false))
+                .read()
+        })
+        .inspect_err(|e| tracing::warn!("Could not read tags of {:?}: {e}", path));
+    let Some(tag) = tagged
+        .as_ref()
+        .ok()
+        .and_then(|file| file.primary_tag().or_else(|| file.first_tag()))
+    else {
+        return (
+            fallback_title(),
+            vec!["Unknown".to_string()],
+            Tags::default(),
+        );
+    };
+
+    let text = |key: ItemKey| {
+        tag.get_string(&key)
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+    let title = text(ItemKey::TrackTitle).unwrap_or_else(fallback_title);
+    // Prefer the multi-value artists tag, and split the usual "A/B" otherwise.
+    let artists_key = if tag.get_string(&ItemKey::TrackArtists).is_some() {
+        ItemKey::TrackArtists
+    } else {
+        ItemKey::TrackArtist
+    };
+    let mut artists: Vec<String> = tag
+        .get_strings(&artists_key)
+        .flat_map(|v| v.split('/'))
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect();
+    artists.dedup();
+    if artists.is_empty() {
+        artists.push("Unknown".to_string());
+    }
+
+    let tags = Tags {
+        album: text(ItemKey::AlbumTitle),
+        album_artist: text(ItemKey::AlbumArtist),
+        track: tag.track(),
+        track_total: tag.track_total(),
+        disc: tag.disk(),
+        disc_total: tag.disk_total(),
+        date: text(ItemKey::RecordingDate).or_else(|| text(ItemKey::Year)),
+        year: tag.year(),
+        genres: tag
+            .get_strings(&ItemKey::Genre)
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect(),
+        composer: text(ItemKey::Composer),
+        comment: text(ItemKey::Comment),
+        musicbrainz: MusicBrainzIds {
+            recording: text(ItemKey::MusicBrainzRecordingId),
+            track: text(ItemKey::MusicBrainzTrackId),
+            release: text(ItemKey::MusicBrainzReleaseId),
+            release_group: text(ItemKey::MusicBrainzReleaseGroupId),
+            artist: text(ItemKey::MusicBrainzArtistId),
+            album_artist: text(ItemKey::MusicBrainzReleaseArtistId),
+        },
+    };
+    (title, artists, tags)
+}
+
+/// Brings the index in line with what is under `targets`, which are library roots,
+/// directories in them or single files: files whose mtime or size changed are probed again,
+/// new ones are added and deleted ones dropped, along with their queue entries. Everything
+/// else is left as loaded from `index.json`.
+///
+/// Probing runs on the blocking pool, several files at a time. The index is saved every
+/// `CHECKPOINT_EVERY` songs, and since saved songs carry their stamp, a scan that was cut
+/// short picks up after them the next time. Progress shows in `/library/scan`. Once done,
+/// songs without ReplayGain get their loudness measured in the background.
+///
+/// Returns `false` without doing anything if another rescan is running.
+pub async fn rescan(state: web::Data<State>, targets: Vec<PathBuf>) -> bool {
+    let library = {
+        let mut state = state.lock().await;
+        if state.scan.as_ref().is_some_and(|scan| scan.running) {
+            return false;
         }
-        if let Some(rev) = meta_opt.current() {
-            // rev.tags() returns an iterator of tags; tag.key and tag.value are Options
-            for tag in rev.tags() {
-                let key = tag.key.to_string();
-                let val = tag.value.to_string();
-                match key.to_lowercase().as_str() {
-                    "title" | "tit2" if !val.is_empty() => title = val.to_string(),
-                    "artist" | "tpe1" if !val.is_empty() => artist = val.to_string(),
-                    _ => {}
+        state.scan = Some(ScanProgress {
+            running: true,
+            ..Default::default()
+        });
+        state.library.clone()
+    };
+    let started = Instant::now();
+
+    let (walked, walk_library) = (targets.clone(), library.clone());
+    let files = match tokio::task::spawn_blocking(move || {
+        walked
+            .iter()
+            .flat_map(|target| walk(&walk_library, target))
+            .collect::<Vec<_>>()
+    })
+    .await
+    {
+        Ok(files) => files,
+        Err(e) => {
+            tracing::error!("Rescan failed: {e}");
+            state.lock().await.scan = None;
+            return true;
+        }
+    };
+
+    let (to_probe, removed) = {
+        let mut state = state.lock().await;
+        let on_disk: HashMap<&Path, &Found> = files.iter().map(|f| (f.path.as_path(), f)).collect();
+        let mut unchanged = HashSet::new();
+        let mut previous = HashMap::new();
+        let mut removed = HashMap::new();
+        for song in state.index.values_mut() {
+            match on_disk.get(song.path.as_path()) {
+                Some(found) if song.stamp.is_some() && song.stamp == found.stamp => {
+                    // The root may have been renamed in the settings.
+                    song.root.clone_from(&found.root);
+                    song.file.clone_from(&found.file);
+                    unchanged.insert(song.path.clone());
+                }
+                Some(_) => {
+                    previous.insert(song.path.clone(), song.id);
+                }
+                // Also drops songs from roots taken out of the settings.
+                None if targets.iter().any(|target| song.path.starts_with(target))
+                    || library.locate(&song.path).is_none() =>
+                {
+                    removed.insert(song.id, song.path.clone());
                 }
+                None => {}
             }
         }
+        let to_probe: Vec<Found> = files
+            .iter()
+            .filter(|f| !unchanged.contains(&f.path))
+            .map(|f| Found {
+                previous: previous.get(&f.path).copied(),
+                ..f.clone()
+            })
+            .collect();
 
-        if let Some(track) = format.tracks().first() {
-            if let (Some(tb), Some(n_frames)) =
-                (track.codec_params.time_base, track.codec_params.n_frames)
-            {
-                let ts: TimeStamp = n_frames as TimeStamp;
-                let time = tb.calc_time(ts); // has .seconds (u64) and .frac (f64)
-                duration = Duration::from_secs(time.seconds)
-                    + Duration::from_millis((time.frac * 1000.) as u64)
+        if let Some(scan) = &mut state.scan {
+            scan.seen = files.len();
+            scan.skipped = unchanged.len();
+            scan.to_probe = to_probe.len();
+            scan.removed = removed.len();
+        }
+        (to_probe, removed)
+    };
+    tracing::info!(
+        "Rescan found {} songs, {} new or changed, {} gone.",
+        files.len(),
+        to_probe.len(),
+        removed.len()
+    );
+
+    let workers = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let total = to_probe.len();
+    let mut results = futures_util::stream::iter(to_probe)
+        .map(|found| {
+            tokio::task::spawn_blocking(move || {
+                let (path, previous) = (found.path.clone(), found.previous);
+                probe_song(found)
+                    .map(|song| (song, previous))
+                    .map_err(|e| (path, e))
+            })
+        })
+        .buffer_unordered(workers);
+
+    let mut probed = Vec::with_capacity(PROBE_BATCH);
+    // Songs that turned up again, maybe under another path, so they aren't removed below.
+    let mut seen_ids = HashSet::new();
+    let (mut done, mut failed, mut since_checkpoint) = (0, 0, 0);
+    while let Some(result) = results.next().await {
+        done += 1;
+        match result {
+            Ok(Ok(song)) => probed.push(song),
+            Ok(Err((path, e))) => {
+                tracing::warn!("Skipping {:?}: {e}", path);
+                failed += 1;
+            }
+            Err(e) => {
+                tracing::error!("Probe task failed: {e}");
+                failed += 1;
             }
         }
+        if probed.len() < PROBE_BATCH && done < total {
+            continue;
+        }
 
-        let id = uuid::Uuid::new_v5(&Uuid::NAMESPACE_URL, path.display().to_string().as_bytes());
-        let artists = artist
-            .split('/')
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>();
+        let mut state = state.lock().await;
+        since_checkpoint += probed.len();
+        seen_ids.extend(insert_probed(&mut state, probed.drain(..), &removed));
+        if let Some(scan) = &mut state.scan {
+            scan.probed = done - failed;
+            scan.failed = failed;
+            scan.eta = Some(
+                started
+                    .elapsed()
+                    .mul_f64((total - done) as f64 / done as f64),
+            );
+        }
+        if since_checkpoint >= CHECKPOINT_EVERY {
+            since_checkpoint = 0;
+            let (index, aliases) = (state.index.clone(), state.aliases.clone());
+            drop(state);
+            if let Err(e) = save_index(index, aliases).await {
+                tracing::error!("Could not save index: {e}");
+            }
+        }
+    }
 
-        let songmeta = SongMeta {
-            id,
-            title,
-            artists,
-            duration: duration,
-            path,
-        };
+    // Only now that the probes are in, since a file that was moved is gone from its old path
+    // but keeps its ID at the new one.
+    let state_data = state.clone();
+    let mut state = state.lock().await;
+    for id in removed.keys().filter(|id| !seen_ids.contains(*id)) {
+        state.index.remove(id);
+    }
+    state.sync_queue().await;
+    if let Some(scan) = &mut state.scan {
+        scan.running = false;
+        scan.eta = None;
+    }
+    tracing::info!(
+        "Indexed {} songs in {:.1?}.",
+        state.index.len(),
+        started.elapsed()
+    );
+    state.events.emit(Event::IndexRescanned {
+        songs: state.index.len(),
+    });
+    let (index, aliases) = (state.index.clone(), state.aliases.clone());
+    drop(state);
+    if let Err(e) = save_index(index, aliases).await {
+        tracing::error!("Could not save index: {e}");
+    }
+    tokio::spawn(fill_missing_loudness(state_data));
+    true
+}
 
-        index.insert(id, songmeta);
+/// Puts freshly probed songs in the index and returns their IDs. A song that was indexed
+/// under another ID, a path based one or one from before its audio changed, keeps answering
+/// to it through an alias. Measured loudness is expensive, so it is carried over from the
+/// old entry when the file has no ReplayGain tags.
+///
+/// `gone` holds the songs whose file the rescan found missing, by ID. Anything else in the
+/// index counts as still there, which keeps the disk out of this while the state is locked.
+fn insert_probed(
+    state: &mut StateStruct,
+    songs: impl Iterator<Item = (SongMeta, Option<Uuid>)>,
+    gone: &HashMap<Uuid, PathBuf>,
+) -> Vec<Uuid> {
+    let mut ids = Vec::new();
+    for (mut song, mut previous) in songs {
+        // The same audio at another path that is still there is a copy rather than a move.
+        // Copies get an ID from their path as well, so one that moved gets a new one.
+        if let Some(other) = state.index.get(&song.id)
+            && other.path != song.path
+            && !gone.contains_key(&other.id)
+        {
+            let content = song.id;
+            let copy_id = |path: &Path| Uuid::new_v5(&content, path.as_os_str().as_encoded_bytes());
+            song.id = copy_id(&song.path);
+            if previous.is_none() {
+                previous = gone
+                    .iter()
+                    .find(|&(&id, path)| {
+                        id != song.id && id == copy_id(path) && state.index.contains_key(&id)
+                    })
+                    .map(|(&id, _)| id);
+            }
+        }
+
+        let old = match previous.filter(|&previous| previous != song.id) {
+            Some(previous) => {
+                state.aliases.insert(previous, song.id);
+                state.index.remove(&previous)
+            }
+            None => state.index.get(&song.id).cloned(),
+        };
+        if song.replaygain.track_gain.is_none()
+            && song.replaygain.album_gain.is_none()
+            && let Some(old) = old
+        {
+            song.replaygain = old.replaygain;
+        }
+        state.aliases.remove(&song.id);
+        ids.push(song.id);
+        state.index.insert(song.id, song);
     }
+    ids
+}
 
-    tracing::info!("Indexed {} songs.", index.len());
-    save_index(&index).await?;
-    Ok(())
+/// Parses tag values like "-6.54 dB" or "0.988553".
+fn parse_replaygain(val: &str) -> Option<f32> {
+    val.trim()
+        .trim_end_matches("dB")
+        .trim_end_matches("db")
+        .trim()
+        .parse()
+        .ok()
 }
 
-pub async fn load_index() -> std::io::Result<SongIndex> {
+/// Bumped whenever songs gain fields that only a new probe fills in.
+const INDEX_VERSION: u32 = 3;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredIndex {
+    Versioned {
+        version: u32,
+        songs: SongIndex,
+        #[serde(default)]
+        aliases: SongAliases,
+    },
+    /// Before the index had a version, it was just the songs.
+    Bare(SongIndex),
+}
+
+#[derive(Serialize)]
+struct IndexFile<'a> {
+    version: u32,
+    songs: &'a SongIndex,
+    aliases: &'a SongAliases,
+}
+
+/// Loads `index.json`. Songs from an older version lose their stamp, so the next rescan
+/// probes them again for what's new.
+pub async fn load_index() -> std::io::Result<(SongIndex, SongAliases)> {
     let index_file = dirs::config_dir()
         .unwrap_or_else(|| {
             tracing::error!("No config dir.");
@@ -129,11 +566,27 @@ pub async fn load_index() -> std::io::Result<SongIndex> {
         .join("V3")
         .join("index.json");
     let data = tokio::fs::read_to_string(index_file).await?;
-    let index: SongIndex = serde_json::from_str(&data)?;
-    Ok(index)
+    let (version, mut index, aliases) = match serde_json::from_str(&data)? {
+        StoredIndex::Versioned {
+            version,
+            songs,
+            aliases,
+        } => (version, songs, aliases),
+        StoredIndex::Bare(songs) => (1, songs, SongAliases::new()),
+    };
+    if version < INDEX_VERSION {
+        tracing::info!("Upgrading the index from version {version} to {INDEX_VERSION}.");
+        for song in index.values_mut() {
+            song.stamp = None;
+        }
+    }
+    Ok((index, aliases))
 }
 
-pub async fn save_index(index: &SongIndex) -> std::io::Result<()> {
+/// Writes `index.json` from a copy of the index, so the state lock is not held meanwhile.
+/// It goes to a temporary file first and is renamed over the old one, a crash halfway
+/// leaves the last complete save.
+pub async fn save_index(index: SongIndex, aliases: SongAliases) -> std::io::Result<()> {
     let configdir = dirs::config_dir()
         .unwrap_or_else(|| {
             tracing::error!("No config dir.");
@@ -143,7 +596,250 @@ pub async fn save_index(index: &SongIndex) -> std::io::Result<()> {
         .join("V3");
     tokio::fs::create_dir_all(&configdir).await?;
     let index_file = configdir.join("index.json");
-    let data = serde_json::to_string_pretty(index)?;
-    tokio::fs::write(index_file, data).await?;
-    Ok(())
+    let data = tokio::task::spawn_blocking(move || {
+        serde_json::to_vec_pretty(&IndexFile {
+            version: INDEX_VERSION,
+            songs: &index,
+            aliases: &aliases,
+        })
+    })
+    .await??;
+
+    replace_file(&index_file, &data).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::get_codecs;
+    use symphonia::core::codecs::DecoderOptions;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join(name)
+    }
+
+    /// Probes a fixture the way a rescan does, then decodes all of it and returns the song
+    /// and how many frames came out.
+    fn smoke(name: &str) -> (SongMeta, u64) {
+        let path = fixture(name);
+        assert!(is_audio_file(&path));
+        let metadata = std::fs::metadata(&path).unwrap();
+        let found = Found {
+            stamp: file_stamp(&metadata),
+            path: path.clone(),
+            root: "fixtures".to_string(),
+            file: PathBuf::from(name),
+            previous: None,
+        };
+        let song = probe_song(found).unwrap();
+
+        let file = Box::new(std::fs::File::open(&path).unwrap());
+        let mss = MediaSourceStream::new(file, Default::default());
+        let mut format = get_probe()
+            .format(
+                &Default::default(),
+                mss,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .unwrap()
+            .format;
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .unwrap();
+        let track_id = track.id;
+        let mut decoder = get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .unwrap();
+        let mut frames = 0;
+        loop {
+            match format.next_packet() {
+                Ok(packet) if packet.track_id() == track_id => {
+                    frames += decoder.decode(&packet).unwrap().frames() as u64;
+                }
+                Ok(_) => {}
+                Err(SymphoniaError::IoError(_)) => break,
+                Err(e) => panic!("{name}: {e}"),
+            }
+        }
+        (song, frames)
+    }
+
+    /// The tone fixtures are a quarter second of 440 Hz at 8 kHz, mono.
+    fn check_tone(name: &str) {
+        let (song, frames) = smoke(name);
+        assert_eq!(frames, 2000, "{name}");
+        assert_eq!(song.duration, Duration::from_millis(250), "{name}");
+        assert_eq!(song.title, "tone");
+    }
+
+    #[test]
+    fn wav() {
+        check_tone("tone.wav");
+    }
+
+    #[test]
+    fn aiff() {
+        check_tone("tone.aiff");
+    }
+
+    #[test]
+    fn flac() {
+        check_tone("tone.flac");
+    }
+
+    #[test]
+    fn ogg() {
+        check_tone("tone.ogg");
+    }
+
+    #[test]
+    fn m4a() {
+        check_tone("tone.m4a");
+    }
+
+    #[test]
+    fn mp3() {
+        let (_, frames) = smoke("silence.mp3");
+        assert!(frames > 0);
+    }
+
+    /// Hands out `packets` one byte each, then fails with `error`.
+    struct BrokenReader {
+        packets: Vec<u8>,
+        error: fn() -> SymphoniaError,
+        metadata: symphonia::core::meta::MetadataLog,
+        source: MediaSourceStream,
+    }
+
+    impl BrokenReader {
+        fn new(packets: &[u8], error: fn() -> SymphoniaError) -> Self {
+            BrokenReader {
+                packets: packets.iter().rev().copied().collect(),
+                error,
+                metadata: Default::default(),
+                source: MediaSourceStream::new(
+                    Box::new(std::io::Cursor::new(Vec::new())),
+                    Default::default(),
+                ),
+            }
+        }
+    }
+
+    impl FormatReader for BrokenReader {
+        fn try_new(
+            _: MediaSourceStream,
+            _: &FormatOptions,
+        ) -> symphonia::core::errors::Result<Self> {
+            Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into())
+        }
+        fn cues(&self) -> &[symphonia::core::formats::Cue] {
+            &[]
+        }
+        fn metadata(&mut self) -> symphonia::core::meta::Metadata<'_> {
+            self.metadata.metadata()
+        }
+        fn seek(
+            &mut self,
+            _: symphonia::core::formats::SeekMode,
+            _: symphonia::core::formats::SeekTo,
+        ) -> symphonia::core::errors::Result<symphonia::core::formats::SeekedTo> {
+            Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into())
+        }
+        fn tracks(&self) -> &[symphonia::core::formats::Track] {
+            &[]
+        }
+        fn next_packet(
+            &mut self,
+        ) -> symphonia::core::errors::Result<symphonia::core::formats::Packet> {
+            match self.packets.pop() {
+                Some(byte) => Ok(symphonia::core::formats::Packet::new_from_slice(
+                    0,
+                    0,
+                    1,
+                    &[byte],
+                )),
+                None => Err((self.error)()),
+            }
+        }
+        fn into_inner(self: Box<Self>) -> MediaSourceStream {
+            self.source
+        }
+    }
+
+    fn eof() -> SymphoniaError {
+        SymphoniaError::IoError(std::io::ErrorKind::UnexpectedEof.into())
+    }
+
+    fn corrupt() -> SymphoniaError {
+        SymphoniaError::DecodeError("corrupt")
+    }
+
+    #[test]
+    fn content_id_keeps_what_it_read_before_an_error() {
+        let id = |packets: &[u8], error| content_id(&mut BrokenReader::new(packets, error), 0);
+        assert_eq!(id(&[1, 2], corrupt).unwrap(), id(&[1, 2], eof).unwrap());
+        assert_ne!(id(&[1, 2], corrupt).unwrap(), id(&[1], eof).unwrap());
+        assert!(id(&[], corrupt).is_err());
+    }
+
+    fn found(dir: &Path, name: &str) -> Found {
+        let path = dir.join(name);
+        Found {
+            stamp: file_stamp(&std::fs::metadata(&path).unwrap()),
+            path,
+            root: "music".to_string(),
+            file: PathBuf::from(name),
+            previous: None,
+        }
+    }
+
+    #[test]
+    fn a_moved_copy_keeps_answering_to_its_old_id() {
+        let dir = std::env::temp_dir().join(format!("musicman-copies-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut state = StateStruct::for_tests([]);
+
+        for name in ["a.wav", "b.wav"] {
+            std::fs::copy(fixture("tone.wav"), dir.join(name)).unwrap();
+        }
+        let original = probe_song(found(&dir, "a.wav")).unwrap();
+        let copy = probe_song(found(&dir, "b.wav")).unwrap();
+        assert_eq!(original.id, copy.id);
+        let nothing_gone = HashMap::new();
+        let original_id =
+            insert_probed(&mut state, [(original, None)].into_iter(), &nothing_gone)[0];
+        let copy_id = insert_probed(&mut state, [(copy, None)].into_iter(), &nothing_gone)[0];
+        assert_ne!(original_id, copy_id);
+
+        std::fs::rename(dir.join("b.wav"), dir.join("c.wav")).unwrap();
+        let moved = probe_song(found(&dir, "c.wav")).unwrap();
+        let gone = HashMap::from([(copy_id, dir.join("b.wav"))]);
+        let moved_id = insert_probed(&mut state, [(moved, None)].into_iter(), &gone)[0];
+
+        assert_ne!(moved_id, copy_id);
+        assert!(!state.index.contains_key(&copy_id));
+        assert_eq!(state.resolve(copy_id), moved_id);
+        assert_eq!(state.resolve(original_id), original_id);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "opus")]
+    #[test]
+    fn opus() {
+        // 25 packets of 20 ms, less the 312 frames of pre-skip.
+        let (_, frames) = smoke("silence.opus");
+        assert_eq!(frames, 25 * 960 - 312);
+    }
+
+    #[cfg(not(feature = "opus"))]
+    #[test]
+    fn opus_is_not_indexed_without_the_feature() {
+        assert!(!is_audio_file(&fixture("silence.opus")));
+    }
 }
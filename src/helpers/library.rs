@@ -0,0 +1,70 @@
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::types::LibraryRoot;
+
+/// The library roots from the settings, with their exclude globs compiled.
+pub struct Library {
+    roots: Vec<(LibraryRoot, GlobSet)>,
+}
+
+impl Library {
+    /// Globs that don't parse are logged and left out.
+    pub fn new(roots: &[LibraryRoot]) -> Self {
+        let roots = roots
+            .iter()
+            .map(|root| {
+                let mut builder = GlobSetBuilder::new();
+                for pattern in &root.exclude {
+                    match Glob::new(pattern) {
+                        Ok(glob) => {
+                            builder.add(glob);
+                        }
+                        Err(e) => tracing::error!("Bad exclude glob {pattern:?}: {e}"),
+                    }
+                }
+                let excludes = builder.build().unwrap_or_else(|e| {
+                    tracing::error!("Could not build excludes for {:?}: {e}", root.path);
+                    GlobSet::empty()
+                });
+                (root.clone(), excludes)
+            })
+            .collect::<Vec<_>>();
+
+        for (i, (root, _)) in roots.iter().enumerate() {
+            if roots[..i]
+                .iter()
+                .any(|(other, _)| other.name() == root.name())
+            {
+                tracing::warn!("More than one library root is called {:?}.", root.name());
+            }
+        }
+        Self { roots }
+    }
+
+    pub fn roots(&self) -> impl Iterator<Item = &LibraryRoot> {
+        self.roots.iter().map(|(root, _)| root)
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.roots().map(|root| root.path.clone()).collect()
+    }
+
+    /// The root `path` belongs to, the innermost one if roots are nested, and the path
+    /// relative to it. `None` if it is outside every root or excluded.
+    pub fn locate<'a>(&'a self, path: &'a Path) -> Option<(&'a LibraryRoot, &'a Path)> {
+        let (root, excludes, file) = self
+            .roots
+            .iter()
+            .filter_map(|(root, excludes)| {
+                Some((root, excludes, path.strip_prefix(&root.path).ok()?))
+            })
+            .min_by_key(|(_, _, file)| file.components().count())?;
+        (!excludes.is_match(file)).then_some((root, file))
+    }
+
+    pub fn root(&self, name: &str) -> Option<&LibraryRoot> {
+        self.roots().find(|root| root.name() == name)
+    }
+}
@@ -0,0 +1,276 @@
+use std::{f64::consts::PI, fs::File, path::Path};
+
+use actix_web::web;
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{CODEC_TYPE_NULL, DecoderOptions},
+    errors::Error,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+use uuid::Uuid;
+
+use crate::helpers::{get_codecs, save_index};
+use crate::types::*;
+
+/// ReplayGain 2.0 reference level in LUFS.
+const REFERENCE_LUFS: f64 = -18.0;
+/// Songs analysed between index saves.
+const SAVE_EVERY: usize = 50;
+
+/// One biquad section of the BS.1770 K-weighting filter.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[1] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[2] * y;
+        y
+    }
+}
+
+/// The two K-weighting stages for `rate`, derived the same way libebur128 does so any
+/// sample rate gets the filter the standard specifies at 48 kHz.
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let fs = rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let highpass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, highpass]
+}
+
+/// BS.1770 channel weight, surrounds count a bit more and the LFE not at all.
+fn channel_weight(channels: usize, channel: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (6, 4) | (6, 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+fn lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// Decodes the whole file and measures its EBU R128 integrated loudness and sample peak,
+/// returned as track ReplayGain values against the -18 LUFS reference.
+pub fn analyze_loudness(path: &Path) -> Result<ReplayGain, Box<dyn std::error::Error>> {
+    let file = Box::new(File::open(path)?);
+    let mss = MediaSourceStream::new(file, Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension() {
+        hint.with_extension(ext.to_str().unwrap_or(""));
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut reader = probed.format;
+
+    let track = reader
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("No audio track")?;
+    let mut decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+    let track_id = track.id;
+    let rate = track
+        .codec_params
+        .sample_rate
+        .ok_or("Missing sample rate")?;
+    let channels = track
+        .codec_params
+        .channels
+        .ok_or("Missing channels")?
+        .count();
+
+    let mut filters = vec![k_weighting(rate); channels];
+    // Gating blocks are 400 ms long and start every 100 ms, so keep mean squares per 100 ms.
+    let step = (rate / 10) as usize;
+    let mut step_energy = 0.0;
+    let mut step_frames = 0;
+    let mut steps: Vec<f64> = Vec::new();
+    let mut peak = 0f32;
+
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(p) => p,
+            Err(Error::IoError(_)) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(Error::DecodeError(_)) => continue,
+            Err(Error::IoError(_)) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        let spec = *decoded.spec();
+        let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buf.copy_interleaved_ref(decoded);
+
+        for frame in buf.samples().chunks_exact(channels) {
+            for (c, &s) in frame.iter().enumerate() {
+                peak = peak.max(s.abs());
+                let mut y = s as f64;
+                for filter in &mut filters[c] {
+                    y = filter.process(y);
+                }
+                step_energy += channel_weight(channels, c) * y * y;
+            }
+            step_frames += 1;
+            if step_frames == step {
+                steps.push(step_energy / step as f64);
+                step_energy = 0.0;
+                step_frames = 0;
+            }
+        }
+    }
+
+    let blocks: Vec<f64> = steps
+        .windows(4)
+        .map(|w| w.iter().sum::<f64>() / 4.0)
+        .collect();
+    let above = |threshold: f64| -> Vec<f64> {
+        blocks
+            .iter()
+            .copied()
+            .filter(|&e| e > 0.0 && lufs(e) > threshold)
+            .collect()
+    };
+
+    let gated = above(-70.0);
+    if gated.is_empty() {
+        return Err("Too short or silent to measure".into());
+    }
+    let relative = lufs(gated.iter().sum::<f64>() / gated.len() as f64) - 10.0;
+    let gated = above(relative);
+    let loudness = lufs(gated.iter().sum::<f64>() / gated.len() as f64);
+
+    Ok(ReplayGain {
+        track_gain: Some((REFERENCE_LUFS - loudness) as f32),
+        track_peak: Some(peak),
+        measured: true,
+        ..Default::default()
+    })
+}
+
+/// Measures every indexed song that has no ReplayGain tags, one at a time off the async
+/// runtime, and stores the results in the index so they are only ever computed once. Runs
+/// after every rescan, if it is already running the songs a rescan added are left to it.
+pub async fn fill_missing_loudness(state: web::Data<State>) {
+    {
+        let mut state = state.lock().await;
+        if state.measuring {
+            return;
+        }
+        state.measuring = true;
+    }
+
+    let mut measured = 0;
+    loop {
+        // Looked up again after every pass, rescans may have added songs in the meantime.
+        let missing: Vec<(Uuid, std::path::PathBuf)> = {
+            let mut state = state.lock().await;
+            let missing: Vec<_> = state
+                .index
+                .values()
+                .filter(|song| {
+                    let replaygain = &song.replaygain;
+                    replaygain.track_gain.is_none()
+                        && replaygain.album_gain.is_none()
+                        && !replaygain.measured
+                })
+                .map(|song| (song.id, song.path.clone()))
+                .collect();
+            if missing.is_empty() {
+                state.measuring = false;
+                if measured > 0 {
+                    tracing::info!("Measured loudness of {measured} songs.");
+                    let (index, aliases) = (state.index.clone(), state.aliases.clone());
+                    drop(state);
+                    if let Err(e) = save_index(index, aliases).await {
+                        tracing::error!("Could not save index: {e}");
+                    }
+                }
+                return;
+            }
+            missing
+        };
+        tracing::info!("Measuring loudness of {} songs.", missing.len());
+
+        for (id, path) in missing {
+            let result = {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || {
+                    analyze_loudness(&path).map_err(|e| e.to_string())
+                })
+                .await
+            };
+            let replaygain = match result {
+                Ok(Ok(replaygain)) => Some(replaygain),
+                Ok(Err(e)) => {
+                    tracing::warn!("Could not measure {:?}: {e}", path);
+                    None
+                }
+                Err(e) => {
+                    tracing::error!("Loudness task failed: {e}");
+                    None
+                }
+            };
+
+            let mut state = state.lock().await;
+            if let Some(song) = state.index.get_mut(&id) {
+                match replaygain {
+                    Some(replaygain) => song.replaygain = replaygain,
+                    None => song.replaygain.measured = true,
+                }
+            }
+            measured += 1;
+            if measured % SAVE_EVERY == 0 {
+                let (index, aliases) = (state.index.clone(), state.aliases.clone());
+                drop(state);
+                if let Err(e) = save_index(index, aliases).await {
+                    tracing::error!("Could not save index: {e}");
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,20 @@
+mod codecs;
 mod index;
+mod library;
+mod loudness;
+#[cfg(feature = "opus")]
+mod opus;
 mod playlist;
+mod replace;
+mod session;
+mod settings;
+mod watch;
+pub use codecs::*;
 pub use index::*;
+pub use library::*;
+pub use loudness::*;
 pub use playlist::*;
+pub use replace::*;
+pub use session::*;
+pub use settings::*;
+pub use watch::*;
@@ -0,0 +1,99 @@
+use std::sync::Mutex;
+
+use opus::Channels;
+use symphonia::core::{
+    audio::{AsAudioBufferRef, AudioBuffer, AudioBufferRef, Layout, Signal, SignalSpec},
+    codecs::{
+        CODEC_TYPE_OPUS, CodecDescriptor, CodecParameters, Decoder, DecoderOptions, FinalizeResult,
+    },
+    errors::{Error, Result, unsupported_error},
+    formats::Packet,
+    support_codec,
+};
+
+/// Opus always decodes at 48 kHz.
+const RATE: u32 = 48_000;
+/// The longest an Opus packet can be, 120 ms.
+const MAX_FRAMES: usize = 5760;
+
+/// Symphonia decoder for Opus streams, backed by libopus. Symphonia demuxes Ogg Opus itself
+/// but ships no decoder for it.
+pub struct OpusDecoder {
+    params: CodecParameters,
+    channels: Channels,
+    // libopus decoders are `Send` but not `Sync`, symphonia wants both.
+    decoder: Mutex<opus::Decoder>,
+    /// Frames at the start of the stream that only prime the decoder, the pre-skip from the
+    /// Opus header.
+    delay: u64,
+    pcm: Vec<f32>,
+    buf: AudioBuffer<f32>,
+}
+
+impl Decoder for OpusDecoder {
+    fn try_new(params: &CodecParameters, _options: &DecoderOptions) -> Result<Self> {
+        let (channels, layout) = match params.channels.map(|c| c.count()) {
+            Some(1) => (Channels::Mono, Layout::Mono),
+            Some(2) => (Channels::Stereo, Layout::Stereo),
+            _ => return unsupported_error("opus: only mono and stereo streams are supported"),
+        };
+        let count = channels as usize;
+        let decoder = opus::Decoder::new(RATE, channels)
+            .map_err(|_| Error::DecodeError("opus: could not create decoder"))?;
+
+        Ok(Self {
+            params: params.clone(),
+            channels,
+            decoder: Mutex::new(decoder),
+            delay: params.delay.map_or(0, u64::from),
+            pcm: vec![0.0; MAX_FRAMES * count],
+            buf: AudioBuffer::new(MAX_FRAMES as u64, SignalSpec::new_with_layout(RATE, layout)),
+        })
+    }
+
+    fn supported_codecs() -> &'static [CodecDescriptor] {
+        &[support_codec!(CODEC_TYPE_OPUS, "opus", "Opus")]
+    }
+
+    fn reset(&mut self) {
+        let _ = self.decoder.get_mut().unwrap().reset_state();
+    }
+
+    fn codec_params(&self) -> &CodecParameters {
+        &self.params
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Result<AudioBufferRef<'_>> {
+        self.buf.clear();
+        // An empty packet would ask libopus to make up audio for a lost one.
+        if packet.buf().is_empty() {
+            return Err(Error::DecodeError("opus: empty packet"));
+        }
+        let frames = self
+            .decoder
+            .get_mut()
+            .unwrap()
+            .decode_float(packet.buf(), &mut self.pcm, false)
+            .map_err(|_| Error::DecodeError("opus: corrupt packet"))?;
+
+        // Timestamps count the pre-skip, so whatever of it is in this packet goes, also when
+        // decoding starts again after a seek.
+        let skip = self.delay.saturating_sub(packet.ts()).min(frames as u64) as usize;
+        let count = self.channels as usize;
+        self.buf.render_reserved(Some(frames - skip));
+        for c in 0..count {
+            for (i, s) in self.buf.chan_mut(c).iter_mut().enumerate() {
+                *s = self.pcm[(skip + i) * count + c];
+            }
+        }
+        Ok(self.buf.as_audio_buffer_ref())
+    }
+
+    fn finalize(&mut self) -> FinalizeResult {
+        Default::default()
+    }
+
+    fn last_decoded(&self) -> AudioBufferRef<'_> {
+        self.buf.as_audio_buffer_ref()
+    }
+}
@@ -0,0 +1,48 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::io::AsyncWriteExt;
+
+/// Writes `data` to a temporary file next to `path` and renames it over `path`, so a crash
+/// halfway leaves the old file in place instead of a truncated one.
+pub async fn replace_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    // Concurrent saves of the same file each get their own temporary file.
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let mut temp_file = path.as_os_str().to_owned();
+    temp_file.push(format!(".{}.tmp", NEXT.fetch_add(1, Ordering::Relaxed)));
+
+    let written = async {
+        let mut file = tokio::fs::File::create(&temp_file).await?;
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temp_file, path).await
+    }
+    .await;
+    if written.is_err() {
+        let _ = tokio::fs::remove_file(&temp_file).await;
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn replace_file_leaves_only_the_new_contents() {
+        let dir = std::env::temp_dir().join(format!("musicman-replace-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("session.json");
+
+        replace_file(&path, b"old, and longer").await.unwrap();
+        replace_file(&path, b"new").await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        let names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["session.json"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,35 @@
+use std::process::exit;
+
+use crate::helpers::replace_file;
+use crate::types::*;
+
+pub async fn load_session() -> std::io::Result<Session> {
+    let session_file = dirs::config_dir()
+        .unwrap_or_else(|| {
+            tracing::error!("No config dir.");
+            exit(1)
+        })
+        .join("musicman")
+        .join("V3")
+        .join("session.json");
+    if !tokio::fs::try_exists(&session_file).await? {
+        return Ok(Session::default());
+    }
+    let data = tokio::fs::read_to_string(session_file).await?;
+    let session: Session = serde_json::from_str(&data)?;
+    Ok(session)
+}
+
+pub async fn save_session(session: &Session) -> std::io::Result<()> {
+    let configdir = dirs::config_dir()
+        .unwrap_or_else(|| {
+            tracing::error!("No config dir.");
+            exit(1)
+        })
+        .join("musicman")
+        .join("V3");
+    tokio::fs::create_dir_all(&configdir).await?;
+    let session_file = configdir.join("session.json");
+    let data = serde_json::to_string(session)?;
+    replace_file(&session_file, data.as_bytes()).await
+}
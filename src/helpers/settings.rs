@@ -0,0 +1,35 @@
+use std::process::exit;
+
+use crate::helpers::replace_file;
+use crate::types::*;
+
+pub async fn load_settings() -> std::io::Result<Settings> {
+    let settings_file = dirs::config_dir()
+        .unwrap_or_else(|| {
+            tracing::error!("No config dir.");
+            exit(1)
+        })
+        .join("musicman")
+        .join("V3")
+        .join("settings.json");
+    if !tokio::fs::try_exists(&settings_file).await? {
+        return Ok(Settings::default());
+    }
+    let data = tokio::fs::read_to_string(settings_file).await?;
+    let settings: Settings = serde_json::from_str(&data)?;
+    Ok(settings)
+}
+
+pub async fn save_settings(settings: &Settings) -> std::io::Result<()> {
+    let configdir = dirs::config_dir()
+        .unwrap_or_else(|| {
+            tracing::error!("No config dir.");
+            exit(1)
+        })
+        .join("musicman")
+        .join("V3");
+    tokio::fs::create_dir_all(&configdir).await?;
+    let settings_file = configdir.join("settings.json");
+    let data = serde_json::to_string_pretty(settings)?;
+    replace_file(&settings_file, data.as_bytes()).await
+}
@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use actix_web::web;
+use notify::{
+    EventKind, RecursiveMode, Watcher,
+    event::{AccessKind, AccessMode},
+};
+use tokio::sync::mpsc;
+
+use crate::helpers::rescan;
+use crate::types::State;
+
+/// Quiet time after the last change before a burst of them is applied.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Watches the library roots for files being added, moved, renamed or deleted and rescans
+/// the parts that changed once things have settled.
+pub async fn watch_library(state: web::Data<State>) {
+    let roots = state.lock().await.library.paths();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        match res {
+            // Reads and opens don't change anything, and a download is only done once the
+            // file is closed after writing.
+            Ok(event)
+                if matches!(
+                    event.kind,
+                    EventKind::Create(_)
+                        | EventKind::Remove(_)
+                        | EventKind::Modify(_)
+                        | EventKind::Access(AccessKind::Close(AccessMode::Write))
+                ) =>
+            {
+                for path in event.paths {
+                    tx.send(path).ok();
+                }
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!("Library watch error: {e}"),
+        }
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::error!("Could not watch the library: {e}");
+            return;
+        }
+    };
+    for root in roots {
+        // A root on a mount that isn't up yet is skipped rather than taking the others down.
+        match watcher.watch(&root, RecursiveMode::Recursive) {
+            Ok(()) => tracing::info!("Watching {:?} for changes.", root),
+            Err(e) => tracing::error!("Could not watch {:?}: {e}", root),
+        }
+    }
+
+    let mut dirty: Vec<PathBuf> = Vec::new();
+    loop {
+        let path = if dirty.is_empty() {
+            rx.recv().await
+        } else {
+            match tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+                Ok(path) => path,
+                Err(_) => {
+                    let roots = collapse(std::mem::take(&mut dirty));
+                    // The startup rescan may still be going, try again after the next wait.
+                    if !rescan(state.clone(), roots.clone()).await {
+                        dirty = roots;
+                    }
+                    continue;
+                }
+            }
+        };
+        let Some(path) = path else {
+            break;
+        };
+        dirty.push(path);
+    }
+}
+
+/// Drops duplicates and paths inside other changed directories, which get walked anyway.
+fn collapse(mut paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    paths.sort();
+    paths.dedup();
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for path in paths {
+        if !roots.last().is_some_and(|root| path.starts_with(root)) {
+            roots.push(path);
+        }
+    }
+    roots
+}
@@ -3,13 +3,13 @@ use daemonize::Daemonize;
 use signal_hook::{consts::TERM_SIGNALS, iterator::Signals};
 use std::{
     fs::{File, remove_file},
-    path::PathBuf,
     process::exit,
     sync::Arc,
 };
 use tokio::sync::Mutex;
 
 mod helpers;
+mod mpd;
 mod services;
 mod types;
 mod watcher_thread;
@@ -32,14 +32,8 @@ async fn main() -> std::io::Result<()> {
             exit(1)
         }
     }
-    std::thread::spawn(move || {
-        let mut signals = Signals::new(TERM_SIGNALS).unwrap();
-        for sig in signals.forever() {
-            tracing::info!("Received signal {:?}, cleaning up PID file.", sig);
-            remove_file(PIDFILE).ok();
-            std::process::exit(0);
-        }
-    });
+    // Registered right away, handled once the state exists so it can be saved first.
+    let mut signals = Signals::new(TERM_SIGNALS).unwrap();
     let Ok(port) = std::env::args()
         .nth(1)
         .unwrap_or_else(|| "4400".to_string())
@@ -51,37 +45,146 @@ async fn main() -> std::io::Result<()> {
 
     tracing::info!("Binding to port {port}.");
 
-    let Ok(stream_handle) = rodio::OutputStreamBuilder::open_default_stream() else {
+    // A missing file gives the defaults. A broken one stops here, the defaults would point
+    // the rescan at another library and get saved over the user's file.
+    let settings = match helpers::load_settings().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            tracing::error!("Could not load settings: {e}");
+            exit(1)
+        }
+    };
+
+    let Ok(stream_handle) = open_stream(settings.output_format) else {
         tracing::error!("Could not open a rodio output stream.");
         std::process::exit(1);
     };
     let sink = rodio::Sink::connect_new(stream_handle.mixer());
+    let config = stream_handle.config();
+    tracing::info!(
+        "Playing at {} Hz, {} channels.",
+        config.sample_rate(),
+        config.channel_count()
+    );
+    let (deck, deck_handle) = Deck::new(config.channel_count(), config.sample_rate());
+    sink.append(deck);
 
-    helpers::generate_index(&PathBuf::from(dirs::home_dir().unwrap().join("Music"))).await?;
-    let index = helpers::load_index().await?;
+    // The last index is good enough to start with, the rescan below catches up in the
+    // background.
+    let (index, aliases) = match helpers::load_index().await {
+        Ok(index) => index,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+        // Rebuilt from scratch by the rescan.
+        Err(e) => {
+            tracing::error!("Could not load index, starting with an empty one: {e}");
+            Default::default()
+        }
+    };
+    deck_handle.set_crossfade(settings.crossfade);
+    let shuffle = settings.shuffle.then(|| Shuffle::new(0, None));
+    let events = Arc::new(Events::default());
+    let library = Arc::new(helpers::Library::new(&settings.library));
     let state = web::Data::new(Mutex::new(StateStruct {
         current_idx: 0,
         current_song: None,
         queue: Vec::new(),
-        index: index,
+        next_entry_id: 0,
+        index,
+        aliases,
+        settings,
         sink: Arc::new(sink),
+        deck: deck_handle,
         audio: None,
+        next_audio: None,
+        shuffle,
+        stopped: false,
+        session_key: 0,
+        events: events.clone(),
+        published: Published::default(),
+        library: library.clone(),
+        scan: None,
+        measuring: false,
     }));
 
+    state.lock().await.apply_volume();
+    match helpers::load_session().await {
+        Ok(session) => state.lock().await.restore(session).await,
+        Err(e) => tracing::error!("Could not load session: {e}"),
+    }
+
+    let runtime = tokio::runtime::Handle::current();
+    let state_clone = state.clone();
+    std::thread::spawn(move || {
+        if let Some(sig) = signals.forever().next() {
+            tracing::info!("Received signal {:?}, saving session.", sig);
+            runtime.block_on(async {
+                let session = state_clone.lock().await.to_session();
+                if let Err(e) = helpers::save_session(&session).await {
+                    tracing::error!("Could not save session: {e}");
+                }
+            });
+            tracing::info!("Cleaning up PID file.");
+            remove_file(PIDFILE).ok();
+            std::process::exit(0);
+        }
+    });
+
     let state_clone = state.clone();
     tokio::spawn(async move { watcher_thread::init(state_clone).await });
+    let state_clone = state.clone();
+    tokio::spawn(async move { helpers::rescan(state_clone, library.paths()).await });
+    let state_clone = state.clone();
+    tokio::spawn(async move { helpers::watch_library(state_clone).await });
+
+    if let Some(port) = state.lock().await.settings.mpd_port {
+        tokio::spawn(mpd::listen(port, state.clone(), events.clone()));
+    }
 
+    let events = web::Data::from(events);
     let Ok(server) = HttpServer::new(move || {
         App::new()
             .app_data(state.clone())
+            .app_data(events.clone())
             .service(services::next)
             .service(services::prev)
             .service(services::seek)
             .service(services::clear)
             .service(services::pause)
+            .service(services::crossfade)
+            .service(services::replaygain)
+            .service(services::shuffle)
+            .service(services::repeat)
+            .service(services::single)
+            .service(services::consume)
+            .service(services::volume_get)
+            .service(services::volume_mute)
+            .service(services::volume_unmute)
+            .service(services::volume_set)
             .service(services::search)
+            .service(services::library_roots)
+            .service(services::library_root)
+            .service(services::library_scan_status)
+            .service(services::library_scan)
+            .service(services::library_artists)
+            .service(services::library_artist_albums)
+            .service(services::library_album)
+            .service(services::library_genres)
+            .service(services::library_browse)
             .service(services::status)
+            .service(services::events)
+            .service(services::idle)
             .service(services::enqueue)
+            .service(services::enqueue_next)
+            .service(services::enqueue_dir)
+            .service(services::replace_dir)
+            .service(services::queue_remove)
+            .service(services::queue_remove_range)
+            .service(services::queue_move)
+            .service(services::queue_move_range)
+            .service(services::play)
+            .service(services::queue_remove_id)
+            .service(services::queue_move_id)
+            .service(services::play_id)
             .service(services::albumart)
             .service(services::playlist_get)
             .service(services::playlist_list)
@@ -94,3 +197,24 @@ async fn main() -> std::io::Result<()> {
 
     server.run().await
 }
+
+/// Opens the default device in `format`, or in the device's own format if none is set or the
+/// device won't take it.
+fn open_stream(format: Option<OutputFormat>) -> Result<rodio::OutputStream, rodio::StreamError> {
+    if let Some(format) = format {
+        match rodio::OutputStreamBuilder::from_default_device().and_then(|builder| {
+            builder
+                .with_sample_rate(format.sample_rate)
+                .with_channels(format.channels)
+                .open_stream()
+        }) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => tracing::warn!(
+                "Could not open the device at {} Hz, {} channels: {e}",
+                format.sample_rate,
+                format.channels
+            ),
+        }
+    }
+    rodio::OutputStreamBuilder::open_default_stream()
+}
@@ -0,0 +1,780 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::time::Duration;
+
+use super::{Ack, Context};
+use crate::helpers;
+use crate::types::*;
+
+/// Commands answered, for `commands`.
+const COMMANDS: &[&str] = &[
+    "add",
+    "addid",
+    "clear",
+    "close",
+    "commands",
+    "consume",
+    "currentsong",
+    "delete",
+    "deleteid",
+    "find",
+    "idle",
+    "list",
+    "listplaylists",
+    "lsinfo",
+    "move",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistinfo",
+    "plchanges",
+    "previous",
+    "random",
+    "repeat",
+    "search",
+    "seekcur",
+    "setvol",
+    "single",
+    "stats",
+    "status",
+    "stop",
+    "tagtypes",
+    "urlhandlers",
+];
+
+/// Tags clients can see and filter on, by the lowercase name used in commands and the name
+/// they come back under.
+const TAGS: &[(&str, &str)] = &[
+    ("title", "Title"),
+    ("artist", "Artist"),
+    ("album", "Album"),
+    ("albumartist", "AlbumArtist"),
+    ("track", "Track"),
+    ("disc", "Disc"),
+    ("date", "Date"),
+    ("genre", "Genre"),
+    ("composer", "Composer"),
+];
+
+/// What `song` has for `tag`, one of `TAGS` or `file`.
+fn tag_values(song: &SongMeta, tag: &str) -> Vec<String> {
+    let tags = &song.tags;
+    match tag {
+        "title" => vec![song.title.clone()],
+        "artist" => song.artists.clone(),
+        "album" => tags.album.iter().cloned().collect(),
+        "albumartist" => tags.album_artist.iter().cloned().collect(),
+        "track" => tags.track.iter().map(u32::to_string).collect(),
+        "disc" => tags.disc.iter().map(u32::to_string).collect(),
+        "date" => tags.date.iter().cloned().collect(),
+        "genre" => tags.genres.clone(),
+        "composer" => tags.composer.iter().cloned().collect(),
+        "file" => vec![song.uri()],
+        _ => Vec::new(),
+    }
+}
+
+fn known_tag(tag: &str) -> Result<(), Ack> {
+    if tag == "file" || TAGS.iter().any(|&(name, _)| name == tag) {
+        Ok(())
+    } else {
+        Err(Ack::new(Ack::ARG, format!("Unknown tag type: {tag}")))
+    }
+}
+
+fn song_block(out: &mut String, song: &SongMeta) {
+    let _ = writeln!(out, "file: {}", song.uri());
+    for &(tag, name) in TAGS {
+        for value in tag_values(song, tag) {
+            let _ = writeln!(out, "{name}: {value}");
+        }
+    }
+    let _ = writeln!(out, "Time: {}", song.duration.as_secs());
+    let _ = writeln!(out, "duration: {:.3}", song.duration.as_secs_f64());
+}
+
+fn entry_block(out: &mut String, pos: usize, entry: &QueueEntry) {
+    song_block(out, &entry.song);
+    let _ = writeln!(out, "Pos: {pos}");
+    let _ = writeln!(out, "Id: {}", entry.id);
+}
+
+fn arg(args: &[String], i: usize) -> Result<&str, Ack> {
+    args.get(i)
+        .map(String::as_str)
+        .ok_or_else(|| Ack::new(Ack::ARG, "Missing argument"))
+}
+
+fn number<T: std::str::FromStr>(s: &str) -> Result<T, Ack> {
+    s.parse()
+        .map_err(|_| Ack::new(Ack::ARG, format!("Integer expected: {s}")))
+}
+
+fn flag(args: &[String]) -> Result<bool, Ack> {
+    match arg(args, 0)? {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        other => Err(Ack::new(
+            Ack::ARG,
+            format!("Boolean (0/1) expected: {other}"),
+        )),
+    }
+}
+
+/// Parses `POS` or `START:END` into a half-open range.
+fn range(s: &str) -> Result<(usize, usize), Ack> {
+    match s.split_once(':') {
+        Some((start, end)) => Ok((number(start)?, number(end)?)),
+        None => {
+            let pos = number(s)?;
+            Ok((pos, pos + 1))
+        }
+    }
+}
+
+/// Whether `song` matches every `TAG VALUE` pair, exactly for `find` and as a case
+/// insensitive substring for `search`.
+fn matches(song: &SongMeta, filters: &[(String, String)], exact: bool) -> bool {
+    let test = |value: &str, wanted: &str| {
+        if exact {
+            value == wanted
+        } else {
+            value.to_lowercase().contains(&wanted.to_lowercase())
+        }
+    };
+    filters.iter().all(|(tag, wanted)| match tag.as_str() {
+        "any" => TAGS
+            .iter()
+            .map(|&(tag, _)| tag)
+            .chain(["file"])
+            .any(|tag| tag_values(song, tag).iter().any(|v| test(v, wanted))),
+        tag => tag_values(song, tag).iter().any(|v| test(v, wanted)),
+    })
+}
+
+fn filters(args: &[String]) -> Result<Vec<(String, String)>, Ack> {
+    if args.first().is_some_and(|a| a.starts_with('(')) {
+        return Err(Ack::new(Ack::ARG, "Filter expressions are not supported"));
+    }
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Err(Ack::new(Ack::ARG, "Expected TAG VALUE pairs"));
+    }
+    args.chunks(2)
+        .map(|pair| {
+            let tag = pair[0].to_lowercase();
+            if tag != "any" {
+                known_tag(&tag)?;
+            }
+            Ok((tag, pair[1].clone()))
+        })
+        .collect()
+}
+
+/// Songs at `uri`, one if it names a file, everything below it sorted by path if it names
+/// a directory.
+fn songs_at(index: &SongIndex, target: &str) -> Vec<SongMeta> {
+    let target = target.trim_end_matches('/');
+    let mut songs: Vec<SongMeta> = index
+        .values()
+        .filter(|song| {
+            let file = song.uri();
+            target.is_empty()
+                || file == target
+                || file
+                    .strip_prefix(target)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+        .cloned()
+        .collect();
+    songs.sort_by_cached_key(|s| s.uri());
+    songs
+}
+
+pub async fn execute(name: &str, args: &[String], ctx: &Context) -> Result<String, Ack> {
+    let mut out = String::new();
+    match name {
+        "ping" => {}
+        "commands" => {
+            for command in COMMANDS {
+                let _ = writeln!(out, "command: {command}");
+            }
+        }
+        "notcommands" | "urlhandlers" => {}
+        "tagtypes" => {
+            for (_, name) in TAGS {
+                let _ = writeln!(out, "tagtype: {name}");
+            }
+        }
+        "outputs" => out.push_str("outputid: 0\noutputname: default\noutputenabled: 1\n"),
+
+        "status" => {
+            let mut state = ctx.state.lock().await;
+            // Caught up right away, a client that just changed the queue expects a new version.
+            state.publish_changes();
+            let playing = state.current_song.is_some() && state.audio.is_some() && !state.stopped;
+            let volume = if state.settings.muted {
+                0
+            } else {
+                state.settings.volume
+            };
+            let repeat = state.settings.repeat;
+            let _ = writeln!(out, "volume: {volume}");
+            let _ = writeln!(out, "repeat: {}", (repeat != RepeatMode::Off) as u8);
+            let _ = writeln!(out, "random: {}", state.shuffle.is_some() as u8);
+            let _ = writeln!(
+                out,
+                "single: {}",
+                (state.settings.single || repeat == RepeatMode::One) as u8
+            );
+            let _ = writeln!(out, "consume: {}", state.settings.consume as u8);
+            let _ = writeln!(out, "playlist: {}", state.queue_version());
+            let _ = writeln!(out, "playlistlength: {}", state.queue.len());
+            let _ = writeln!(
+                out,
+                "state: {}",
+                match (playing, state.is_paused()) {
+                    (false, _) => "stop",
+                    (true, true) => "pause",
+                    (true, false) => "play",
+                }
+            );
+            if state.current_song.is_some()
+                && let Some(entry) = state.queue.get(state.current_idx)
+            {
+                let _ = writeln!(out, "song: {}", state.current_idx);
+                let _ = writeln!(out, "songid: {}", entry.id);
+            }
+            if playing && let (Some(audio), Some(song)) = (&state.audio, &state.current_song) {
+                let elapsed = audio.get_position();
+                let _ = writeln!(
+                    out,
+                    "time: {}:{}",
+                    elapsed.as_secs(),
+                    song.duration.as_secs()
+                );
+                let _ = writeln!(out, "elapsed: {:.3}", elapsed.as_secs_f64());
+                let _ = writeln!(out, "duration: {:.3}", song.duration.as_secs_f64());
+            }
+        }
+        "stats" => {
+            let state = ctx.state.lock().await;
+            let artists: BTreeSet<&String> =
+                state.index.values().flat_map(|s| &s.artists).collect();
+            let playtime: Duration = state.index.values().map(|s| s.duration).sum();
+            let _ = writeln!(out, "artists: {}", artists.len());
+            let _ = writeln!(out, "songs: {}", state.index.len());
+            let _ = writeln!(out, "db_playtime: {}", playtime.as_secs());
+        }
+        "currentsong" => {
+            let state = ctx.state.lock().await;
+            if state.current_song.is_some()
+                && let Some(entry) = state.queue.get(state.current_idx)
+            {
+                entry_block(&mut out, state.current_idx, entry);
+            }
+        }
+        "playlistinfo" => {
+            let state = ctx.state.lock().await;
+            let (start, end) = match args.first() {
+                Some(a) => range(a)?,
+                None => (0, state.queue.len()),
+            };
+            for (pos, entry) in state.queue.iter().enumerate() {
+                if (start..end).contains(&pos) {
+                    entry_block(&mut out, pos, entry);
+                }
+            }
+        }
+        "plchanges" => {
+            let version = number(arg(args, 0)?)?;
+            let mut state = ctx.state.lock().await;
+            state.publish_changes();
+            for (pos, entry) in state.queue.iter().enumerate() {
+                if state.queue_changed_since(pos, version) {
+                    entry_block(&mut out, pos, entry);
+                }
+            }
+        }
+
+        "add" | "addid" => {
+            let target = arg(args, 0)?;
+            let mut state = ctx.state.lock().await;
+            let songs = songs_at(&state.index, target);
+            if songs.is_empty() || (name == "addid" && songs.len() > 1) {
+                return Err(Ack::new(Ack::NO_EXIST, "No such song"));
+            }
+            for song in songs {
+                let id = state.push_entry(song);
+                if name == "addid" {
+                    let _ = writeln!(out, "Id: {id}");
+                }
+            }
+        }
+        "clear" => ctx.state.lock().await.clear().await,
+        "delete" => {
+            let (start, end) = range(arg(args, 0)?)?;
+            if !ctx.state.lock().await.remove_range(start, end).await {
+                return Err(Ack::new(Ack::ARG, "Bad song index"));
+            }
+        }
+        "deleteid" => {
+            let id = number(arg(args, 0)?)?;
+            let mut state = ctx.state.lock().await;
+            let Some(pos) = state.entry_pos(id) else {
+                return Err(Ack::new(Ack::NO_EXIST, "No such song"));
+            };
+            state.remove_range(pos, pos + 1).await;
+        }
+        "move" => {
+            let (start, end) = range(arg(args, 0)?)?;
+            let to = number(arg(args, 1)?)?;
+            if !ctx.state.lock().await.move_range(start, end, to) {
+                return Err(Ack::new(Ack::ARG, "Bad song index"));
+            }
+        }
+
+        "play" | "playid" => {
+            let mut state = ctx.state.lock().await;
+            let pos = match (name, args.first()) {
+                ("play", Some(pos)) => Some(number(pos)?),
+                ("playid", Some(id)) => Some(
+                    state
+                        .entry_pos(number(id)?)
+                        .ok_or_else(|| Ack::new(Ack::NO_EXIST, "No such song"))?,
+                ),
+                _ => None,
+            };
+            match pos {
+                Some(pos) if pos >= state.queue.len() => {
+                    return Err(Ack::new(Ack::ARG, "Bad song index"));
+                }
+                Some(pos) => {
+                    if let GetReturn::Ok = state.jump(pos).await {
+                        state.add().await;
+                    }
+                }
+                None if state.stopped => state.pause().await,
+                None if state.is_paused() => state.sink.play(),
+                None => {}
+            }
+        }
+        "pause" => {
+            let mut state = ctx.state.lock().await;
+            let pause = match args.first() {
+                Some(_) => flag(args)?,
+                None => !state.is_paused(),
+            };
+            if state.stopped {
+                if !pause {
+                    state.pause().await;
+                }
+            } else if pause {
+                state.sink.pause();
+            } else {
+                state.sink.play();
+            }
+        }
+        "stop" => ctx.state.lock().await.stop().await,
+        "next" => {
+            let mut state = ctx.state.lock().await;
+            state.next(1).await;
+            state.add().await;
+        }
+        "previous" => {
+            let mut state = ctx.state.lock().await;
+            state.prev(1).await;
+            state.add().await;
+        }
+        "seekcur" => {
+            let time = arg(args, 0)?;
+            let secs: f64 = time
+                .parse()
+                .map_err(|_| Ack::new(Ack::ARG, format!("Number expected: {time}")))?;
+            let mut state = ctx.state.lock().await;
+            let Some(audio) = &mut state.audio else {
+                return Err(Ack::new(Ack::NO_EXIST, "Not playing"));
+            };
+            let target = if time.starts_with('+') || time.starts_with('-') {
+                audio.get_position().as_secs_f64() + secs
+            } else {
+                secs
+            };
+            audio.seek(Duration::from_secs_f64(target.max(0.0)));
+        }
+        "setvol" => {
+            let volume = number(arg(args, 0)?)?;
+            ctx.state.lock().await.set_volume(volume).await;
+        }
+        "random" => {
+            let on = flag(args)?;
+            let mut state = ctx.state.lock().await;
+            if state.shuffle.is_some() != on {
+                state.set_shuffle(on).await;
+            }
+        }
+        "repeat" => {
+            let repeat = if flag(args)? {
+                RepeatMode::All
+            } else {
+                RepeatMode::Off
+            };
+            ctx.state.lock().await.set_repeat(repeat).await;
+        }
+        "single" => {
+            let single = flag(args)?;
+            ctx.state.lock().await.set_single(single).await;
+        }
+        "consume" => {
+            let consume = flag(args)?;
+            ctx.state.lock().await.set_consume(consume).await;
+        }
+
+        "search" | "find" => {
+            let filters = filters(args)?;
+            let state = ctx.state.lock().await;
+            let mut songs: Vec<&SongMeta> = state
+                .index
+                .values()
+                .filter(|song| matches(song, &filters, name == "find"))
+                .collect();
+            songs.sort_by_cached_key(|s| s.uri());
+            for song in songs {
+                song_block(&mut out, song);
+            }
+        }
+        "list" => {
+            let tag = arg(args, 0)?.to_lowercase();
+            known_tag(&tag)?;
+            let key = TAGS
+                .iter()
+                .find(|&&(name, _)| name == tag)
+                .map_or("file", |&(_, key)| key);
+            let filters = match &args[1..] {
+                [] => Vec::new(),
+                // The old `list album ARTIST` form.
+                [artist] if tag == "album" => vec![("artist".to_string(), artist.clone())],
+                rest => filters(rest)?,
+            };
+            let state = ctx.state.lock().await;
+            let values: BTreeSet<String> = state
+                .index
+                .values()
+                .filter(|song| matches(song, &filters, true))
+                .flat_map(|song| tag_values(song, &tag))
+                .collect();
+            for value in values {
+                let _ = writeln!(out, "{key}: {value}");
+            }
+        }
+        "lsinfo" => {
+            let target = args.first().map_or("", |a| a.trim_matches('/'));
+            let state = ctx.state.lock().await;
+            let songs = songs_at(&state.index, target);
+            if songs.is_empty() && !target.is_empty() {
+                return Err(Ack::new(Ack::NO_EXIST, "No such directory"));
+            }
+
+            let mut directories = BTreeSet::new();
+            for song in &songs {
+                let file = song.uri();
+                let rest = match target {
+                    "" => file.as_str(),
+                    _ if file == target => {
+                        song_block(&mut out, song);
+                        continue;
+                    }
+                    _ => &file[target.len() + 1..],
+                };
+                match rest.split_once('/') {
+                    Some((dir, _)) if target.is_empty() => {
+                        directories.insert(dir.to_string());
+                    }
+                    Some((dir, _)) => {
+                        directories.insert(format!("{target}/{dir}"));
+                    }
+                    None => song_block(&mut out, song),
+                }
+            }
+            let mut listing = String::new();
+            for dir in directories {
+                let _ = writeln!(listing, "directory: {dir}");
+            }
+            out.insert_str(0, &listing);
+            drop(state);
+
+            if target.is_empty()
+                && let Ok(playlists) = helpers::get_all_playlists().await
+            {
+                for playlist in playlists {
+                    let _ = writeln!(out, "playlist: {}", playlist.name);
+                }
+            }
+        }
+        "listplaylists" => {
+            let playlists = helpers::get_all_playlists()
+                .await
+                .map_err(|e| Ack::new(Ack::NO_EXIST, e.to_string()))?;
+            for playlist in playlists {
+                let _ = writeln!(out, "playlist: {}", playlist.name);
+            }
+        }
+
+        _ => {
+            return Err(Ack::new(
+                Ack::UNKNOWN,
+                format!("unknown command \"{name}\""),
+            ));
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpd::tests::{context, context_with, send};
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn range_takes_a_position_or_a_span() {
+        assert_eq!(range("3").ok(), Some((3, 4)));
+        assert_eq!(range("1:4").ok(), Some((1, 4)));
+        assert_eq!(range("x").err().map(|ack| ack.code), Some(Ack::ARG));
+        assert_eq!(range("1:").err().map(|ack| ack.code), Some(Ack::ARG));
+    }
+
+    #[test]
+    fn filters_come_in_tag_value_pairs() {
+        let pairs = filters(&words(&["Artist", "Low", "title", "Words"])).ok();
+        assert_eq!(
+            pairs,
+            Some(vec![
+                ("artist".to_string(), "Low".to_string()),
+                ("title".to_string(), "Words".to_string()),
+            ])
+        );
+        assert!(filters(&words(&[])).is_err());
+        assert!(filters(&words(&["artist"])).is_err());
+        assert!(filters(&words(&["(artist == 'Low')"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn lsinfo_lists_directories_before_songs() {
+        let ctx = context();
+        assert_eq!(
+            send(&ctx, "lsinfo music").await,
+            "directory: music/a\n\
+             file: music/c.flac\nTitle: Three\nArtist: Crowd\nTime: 180\nduration: 180.000\n\
+             OK\n"
+        );
+        assert_eq!(
+            send(&ctx, "lsinfo music/a/").await,
+            "directory: music/a/b\n\
+             file: music/a/one.flac\nTitle: One\nArtist: Low\nTime: 180\nduration: 180.000\n\
+             OK\n"
+        );
+        assert_eq!(
+            send(&ctx, "lsinfo music/a/b/two.flac").await,
+            "file: music/a/b/two.flac\nTitle: Two\nArtist: Low\nTime: 180\nduration: 180.000\n\
+             OK\n"
+        );
+        assert_eq!(
+            send(&ctx, "lsinfo music/ab").await,
+            "ACK [50@0] {lsinfo} No such directory\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn add_queues_a_directory_in_path_order() {
+        let ctx = context();
+        assert_eq!(send(&ctx, "add music/a").await, "OK\n");
+        assert_eq!(
+            send(&ctx, "addid music/a").await,
+            "ACK [50@0] {addid} No such song\n"
+        );
+        assert_eq!(send(&ctx, "addid music/c.flac").await, "Id: 3\nOK\n");
+        assert_eq!(
+            send(&ctx, "playlistinfo 1:3").await,
+            "file: music/a/one.flac\nTitle: One\nArtist: Low\nTime: 180\nduration: 180.000\n\
+             Pos: 1\nId: 2\n\
+             file: music/c.flac\nTitle: Three\nArtist: Crowd\nTime: 180\nduration: 180.000\n\
+             Pos: 2\nId: 3\n\
+             OK\n"
+        );
+
+        assert_eq!(send(&ctx, "delete 0:2").await, "OK\n");
+        assert_eq!(
+            send(&ctx, "delete 5").await,
+            "ACK [2@0] {delete} Bad song index\n"
+        );
+        let state = ctx.state.lock().await;
+        let left: Vec<u64> = state.queue.iter().map(|entry| entry.id).collect();
+        assert_eq!(left, [3]);
+    }
+
+    /// The entries `plchanges` lists as `(Pos, file)`.
+    async fn plchanges(ctx: &Context, version: u64) -> Vec<(String, String)> {
+        let out = send(ctx, &format!("plchanges {version}")).await;
+        let value = |line: &str, key: &str| line.strip_prefix(key).map(str::to_string);
+        let files = out.lines().filter_map(|line| value(line, "file: "));
+        let positions = out.lines().filter_map(|line| value(line, "Pos: "));
+        positions.zip(files).collect()
+    }
+
+    async fn playlist_version(ctx: &Context) -> String {
+        let status = send(ctx, "status").await;
+        let line = status.lines().find(|line| line.starts_with("playlist: "));
+        line.unwrap()["playlist: ".len()..].to_string()
+    }
+
+    #[tokio::test]
+    async fn plchanges_lists_entries_new_at_their_position() {
+        let ctx = context();
+        assert_eq!(playlist_version(&ctx).await, "0");
+        send(&ctx, "add music/a").await;
+        assert_eq!(playlist_version(&ctx).await, "1");
+        assert_eq!(plchanges(&ctx, 0).await.len(), 2);
+        assert_eq!(plchanges(&ctx, 1).await, []);
+
+        // Other changes leave it alone.
+        ctx.state.lock().await.settings.consume = true;
+        assert!(send(&ctx, "status").await.contains("consume: 1\n"));
+        assert_eq!(playlist_version(&ctx).await, "1");
+
+        send(&ctx, "add music/c.flac").await;
+        let c = ("2".to_string(), "music/c.flac".to_string());
+        assert_eq!(plchanges(&ctx, 1).await, [c]);
+
+        // Everything after a deleted entry moves up.
+        send(&ctx, "delete 0").await;
+        let moved = plchanges(&ctx, 2).await;
+        assert_eq!(
+            moved
+                .iter()
+                .map(|(pos, _)| pos.as_str())
+                .collect::<Vec<_>>(),
+            ["0", "1"]
+        );
+        // A version this run never had, the client is from before a restart.
+        assert_eq!(plchanges(&ctx, 99).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn find_is_exact_and_search_is_not() {
+        let ctx = context();
+        assert_eq!(send(&ctx, "find artist low").await, "OK\n");
+        assert_eq!(
+            send(&ctx, "find artist Low title One").await,
+            "file: music/a/one.flac\nTitle: One\nArtist: Low\nTime: 180\nduration: 180.000\n\
+             OK\n"
+        );
+        assert_eq!(
+            send(&ctx, "search any \"O\"").await,
+            "file: music/a/b/two.flac\nTitle: Two\nArtist: Low\nTime: 180\nduration: 180.000\n\
+             file: music/a/one.flac\nTitle: One\nArtist: Low\nTime: 180\nduration: 180.000\n\
+             file: music/c.flac\nTitle: Three\nArtist: Crowd\nTime: 180\nduration: 180.000\n\
+             OK\n"
+        );
+        assert_eq!(
+            send(&ctx, "list artist").await,
+            "Artist: Crowd\nArtist: Low\nOK\n"
+        );
+        assert_eq!(
+            send(&ctx, "list title artist Crowd").await,
+            "Title: Three\nOK\n"
+        );
+    }
+
+    fn album_track(file: &str, title: &str, artist: &str, track: u32, genre: &str) -> SongMeta {
+        let mut song = SongMeta::for_tests(file, title, artist);
+        song.tags = Tags {
+            album: Some("Things We Lost".to_string()),
+            album_artist: Some("Low".to_string()),
+            track: Some(track),
+            disc: Some(1),
+            date: Some("2001".to_string()),
+            genres: vec![genre.to_string()],
+            ..Default::default()
+        };
+        song
+    }
+
+    #[tokio::test]
+    async fn songs_show_and_match_their_tags() {
+        let mut other = SongMeta::for_tests("y/3.flac", "Other", "Crowd");
+        other.tags.album = Some("Crowded".to_string());
+        other.tags.composer = Some("Someone".to_string());
+        let ctx = context_with([
+            album_track("x/1.flac", "Sunflower", "Low", 1, "Slowcore"),
+            album_track("x/2.flac", "Whitetail", "Guest", 2, "Indie"),
+            other,
+        ]);
+
+        assert_eq!(
+            send(&ctx, "lsinfo music/x/2.flac").await,
+            "file: music/x/2.flac\nTitle: Whitetail\nArtist: Guest\nAlbum: Things We Lost\n\
+             AlbumArtist: Low\nTrack: 2\nDisc: 1\nDate: 2001\nGenre: Indie\n\
+             Time: 180\nduration: 180.000\nOK\n"
+        );
+        assert!(
+            send(&ctx, "tagtypes")
+                .await
+                .contains("tagtype: AlbumArtist\n")
+        );
+
+        let files = |out: String| {
+            out.lines()
+                .filter_map(|line| line.strip_prefix("file: music/"))
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            files(send(&ctx, "find albumartist Low").await),
+            ["x/1.flac", "x/2.flac"]
+        );
+        assert_eq!(files(send(&ctx, "find artist Low").await), ["x/1.flac"]);
+        assert_eq!(files(send(&ctx, "search album crowd").await), ["y/3.flac"]);
+        assert_eq!(files(send(&ctx, "search genre indie").await), ["x/2.flac"]);
+        assert_eq!(files(send(&ctx, "search any someone").await), ["y/3.flac"]);
+        assert_eq!(
+            send(&ctx, "find mood calm").await,
+            "ACK [2@0] {find} Unknown tag type: mood\n"
+        );
+
+        assert_eq!(
+            send(&ctx, "list album").await,
+            "Album: Crowded\nAlbum: Things We Lost\nOK\n"
+        );
+        assert_eq!(
+            send(&ctx, "list genre").await,
+            "Genre: Indie\nGenre: Slowcore\nOK\n"
+        );
+        assert_eq!(send(&ctx, "list album Crowd").await, "Album: Crowded\nOK\n");
+        assert_eq!(
+            send(&ctx, "list date genre Indie").await,
+            "Date: 2001\nOK\n"
+        );
+        assert_eq!(
+            send(&ctx, "list mood").await,
+            "ACK [2@0] {list} Unknown tag type: mood\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_commands_are_refused() {
+        let ctx = context();
+        assert_eq!(
+            send(&ctx, "frobnicate").await,
+            "ACK [5@0] {frobnicate} unknown command \"frobnicate\"\n"
+        );
+        assert_eq!(send(&ctx, "").await, "ACK [5@0] {} No command given\n");
+    }
+}
@@ -0,0 +1,482 @@
+use actix_web::web;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, TryRecvError},
+};
+
+use crate::types::*;
+
+mod commands;
+
+/// Protocol version sent in the greeting. Clients use it to decide which commands to try.
+const GREETING: &str = "OK MPD 0.23.0\n";
+
+/// A failed command, sent back as `ACK [code@index] {command} message`.
+pub struct Ack {
+    code: u8,
+    message: String,
+}
+
+impl Ack {
+    const ARG: u8 = 2;
+    const UNKNOWN: u8 = 5;
+    const NO_EXIST: u8 = 50;
+
+    fn new(code: u8, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Everything a command needs to get at.
+pub struct Context {
+    state: web::Data<State>,
+    events: Arc<Events>,
+}
+
+/// Serves MPD clients on `port` until the daemon exits.
+pub async fn listen(port: u16, state: web::Data<State>, events: Arc<Events>) {
+    let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Could not start the MPD listener at {port}: {e}");
+            return;
+        }
+    };
+    tracing::info!("MPD listener bound to port {port}.");
+
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::warn!("MPD accept failed: {e}");
+                continue;
+            }
+        };
+        tracing::info!("MPD client connected from {addr}.");
+        let ctx = Context {
+            state: state.clone(),
+            events: events.clone(),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = serve(stream, ctx).await {
+                tracing::warn!("MPD client {addr} dropped: {e}");
+            }
+        });
+    }
+}
+
+/// Splits a command line into words, honouring MPD's double quotes and backslash escapes.
+fn tokenize(line: &str) -> Result<Vec<String>, Ack> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(words);
+        };
+
+        let mut word = String::new();
+        if first == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c) => word.push(c),
+                        None => return Err(Ack::new(Ack::ARG, "Missing closing '\"'")),
+                    },
+                    Some(c) => word.push(c),
+                    None => return Err(Ack::new(Ack::ARG, "Missing closing '\"'")),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+}
+
+/// Runs one command line, returning its output without the closing `OK`.
+async fn run(line: &str, ctx: &Context) -> Result<String, (String, Ack)> {
+    let words = tokenize(line).map_err(|ack| (String::new(), ack))?;
+    let Some((name, args)) = words.split_first() else {
+        return Err((String::new(), Ack::new(Ack::UNKNOWN, "No command given")));
+    };
+    commands::execute(name, args, ctx)
+        .await
+        .map_err(|ack| (name.clone(), ack))
+}
+
+fn ack_line((command, ack): (String, Ack), index: usize) -> String {
+    format!("ACK [{}@{index}] {{{command}}} {}\n", ack.code, ack.message)
+}
+
+async fn serve(stream: TcpStream, ctx: Context) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    let mut pending = Pending::new(&ctx.events);
+    write.write_all(GREETING.as_bytes()).await?;
+
+    // Lines collected between `command_list_begin` and `command_list_end`, and whether every
+    // command in it gets its own `list_OK`.
+    let mut list: Option<(bool, Vec<String>)> = None;
+
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => line?,
+            // Kept up with between commands too, so a quiet client never lags behind.
+            alive = pending.next() => {
+                if !alive {
+                    return Ok(());
+                }
+                continue;
+            }
+        };
+        let Some(line) = line else {
+            return Ok(());
+        };
+        let line = line.trim().to_string();
+        let reply = match (line.as_str(), &mut list) {
+            ("command_list_begin", None) => {
+                list = Some((false, Vec::new()));
+                continue;
+            }
+            ("command_list_ok_begin", None) => {
+                list = Some((true, Vec::new()));
+                continue;
+            }
+            ("command_list_end", Some(_)) => {
+                let (ok_each, commands) = list.take().unwrap();
+                let mut reply = String::new();
+                let mut failed = false;
+                for (i, command) in commands.iter().enumerate() {
+                    match run(command, &ctx).await {
+                        Ok(out) => {
+                            reply.push_str(&out);
+                            if ok_each {
+                                reply.push_str("list_OK\n");
+                            }
+                        }
+                        Err(err) => {
+                            reply.push_str(&ack_line(err, i));
+                            failed = true;
+                            break;
+                        }
+                    }
+                }
+                if !failed {
+                    reply.push_str("OK\n");
+                }
+                reply
+            }
+            (_, Some((_, commands))) => {
+                commands.push(line);
+                continue;
+            }
+            ("close", None) => return Ok(()),
+            (line, None) if line == "idle" || line.starts_with("idle ") => {
+                match idle(line, &mut pending, &mut lines).await? {
+                    Some(reply) => reply,
+                    None => return Ok(()),
+                }
+            }
+            ("noidle", None) => continue,
+            (line, None) => match run(line, &ctx).await {
+                Ok(out) => out + "OK\n",
+                Err(err) => ack_line(err, 0),
+            },
+        };
+        write.write_all(reply.as_bytes()).await?;
+    }
+}
+
+/// Subsystems that changed since the client was last told. MPD keeps these per connection,
+/// so a change that comes between two `idle`s is reported by the second one.
+struct Pending {
+    receiver: broadcast::Receiver<Versioned>,
+    changed: BTreeSet<&'static str>,
+}
+
+impl Pending {
+    fn new(events: &Events) -> Self {
+        let (_, receiver) = events.subscribe(None);
+        Self {
+            receiver,
+            changed: BTreeSet::new(),
+        }
+    }
+
+    fn note(&mut self, event: &Versioned) {
+        self.changed
+            .extend(event.event.subsystem().map(Subsystem::mpd_name));
+    }
+
+    /// Events that were missed could have been anything.
+    fn lagged(&mut self) {
+        self.changed.extend(Subsystem::ALL.map(Subsystem::mpd_name));
+    }
+
+    /// Waits for the next event. `false` once the daemon is going away.
+    async fn next(&mut self) -> bool {
+        match self.receiver.recv().await {
+            Ok(event) => self.note(&event),
+            Err(RecvError::Lagged(_)) => self.lagged(),
+            Err(RecvError::Closed) => return false,
+        }
+        true
+    }
+
+    /// Takes in the events that came in meanwhile, then removes and returns the changes in
+    /// `wanted`, or all of them if it is empty.
+    fn take(&mut self, wanted: &BTreeSet<String>) -> BTreeSet<&'static str> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(event) => self.note(&event),
+                Err(TryRecvError::Lagged(_)) => self.lagged(),
+                Err(_) => break,
+            }
+        }
+        let (taken, kept) = std::mem::take(&mut self.changed)
+            .into_iter()
+            .partition(|subsystem| wanted.is_empty() || wanted.contains(*subsystem));
+        self.changed = kept;
+        taken
+    }
+}
+
+/// Reports changes to the subsystems named on the `idle` line, or to any, waiting for one if
+/// nothing is pending until it comes or the client sends `noidle`. `None` if the client or
+/// the daemon went away.
+async fn idle(
+    line: &str,
+    pending: &mut Pending,
+    lines: &mut tokio::io::Lines<BufReader<tokio::net::tcp::OwnedReadHalf>>,
+) -> std::io::Result<Option<String>> {
+    let wanted: BTreeSet<String> = match tokenize(line) {
+        Ok(words) => words.into_iter().skip(1).collect(),
+        Err(ack) => return Ok(Some(ack_line(("idle".into(), ack), 0))),
+    };
+
+    let mut changed = pending.take(&wanted);
+    while changed.is_empty() {
+        tokio::select! {
+            alive = pending.next() => if !alive {
+                return Ok(None);
+            },
+            line = lines.next_line() => match line? {
+                // `noidle`, or anything else, ends the wait with nothing changed.
+                Some(_) => return Ok(Some("OK\n".to_string())),
+                None => return Ok(None),
+            },
+        }
+        changed = pending.take(&wanted);
+    }
+
+    let mut reply: String = changed
+        .iter()
+        .map(|subsystem| format!("changed: {subsystem}\n"))
+        .collect();
+    reply.push_str("OK\n");
+    Ok(Some(reply))
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::Lines;
+    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+    /// A library of three songs at `music/a/one.flac`, `music/a/b/two.flac` and `music/c.flac`.
+    pub fn context() -> Context {
+        context_with([
+            SongMeta::for_tests("a/one.flac", "One", "Low"),
+            SongMeta::for_tests("a/b/two.flac", "Two", "Low"),
+            SongMeta::for_tests("c.flac", "Three", "Crowd"),
+        ])
+    }
+
+    pub fn context_with(songs: impl IntoIterator<Item = SongMeta>) -> Context {
+        Context {
+            state: web::Data::new(State::new(StateStruct::for_tests(songs))),
+            events: Arc::new(Events::default()),
+        }
+    }
+
+    /// What a client would read back for `line` sent on its own.
+    pub async fn send(ctx: &Context, line: &str) -> String {
+        match run(line, ctx).await {
+            Ok(out) => out + "OK\n",
+            Err(err) => ack_line(err, 0),
+        }
+    }
+
+    struct Client {
+        lines: Lines<BufReader<OwnedReadHalf>>,
+        write: OwnedWriteHalf,
+    }
+
+    impl Client {
+        /// Serves one connection on a loopback port and connects to it.
+        async fn connect(ctx: Context) -> Self {
+            let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                serve(stream, ctx).await.unwrap();
+            });
+            let (read, write) = TcpStream::connect(addr).await.unwrap().into_split();
+            let mut client = Client {
+                lines: BufReader::new(read).lines(),
+                write,
+            };
+            assert_eq!(client.line().await, GREETING.trim_end());
+            client
+        }
+
+        async fn send(&mut self, text: &str) {
+            self.write.write_all(text.as_bytes()).await.unwrap();
+        }
+
+        async fn line(&mut self) -> String {
+            tokio::time::timeout(Duration::from_secs(5), self.lines.next_line())
+                .await
+                .expect("no reply")
+                .unwrap()
+                .expect("connection closed")
+        }
+
+        /// Lines up to and including the one that ends the reply.
+        async fn reply(&mut self) -> Vec<String> {
+            let mut reply = Vec::new();
+            loop {
+                let line = self.line().await;
+                let done = line == "OK" || line.starts_with("ACK ");
+                reply.push(line);
+                if done {
+                    return reply;
+                }
+            }
+        }
+    }
+
+    fn tokens(line: &str) -> Option<Vec<String>> {
+        tokenize(line).ok()
+    }
+
+    #[test]
+    fn tokenize_honours_quotes_and_escapes() {
+        assert_eq!(
+            tokens("  add   music/a  "),
+            Some(vec!["add".into(), "music/a".into()])
+        );
+        assert_eq!(
+            tokens(r#"find artist "Sigur Rós" title """#),
+            Some(vec![
+                "find".into(),
+                "artist".into(),
+                "Sigur Rós".into(),
+                "title".into(),
+                "".into(),
+            ])
+        );
+        assert_eq!(
+            tokens(r#"add "a \"b\" \\c""#),
+            Some(vec!["add".into(), r#"a "b" \c"#.into()])
+        );
+        assert_eq!(tokens(""), Some(vec![]));
+        assert!(tokens(r#"add "open"#).is_none());
+        assert!(tokens(r#"add "open\"#).is_none());
+    }
+
+    #[tokio::test]
+    async fn command_lists_answer_each_command_or_the_first_failure() {
+        let mut client = Client::connect(context()).await;
+
+        client
+            .send("command_list_ok_begin\nping\nadd music/c.flac\nplaylistinfo\ncommand_list_end\n")
+            .await;
+        assert_eq!(
+            client.reply().await,
+            [
+                "list_OK",
+                "list_OK",
+                "file: music/c.flac",
+                "Title: Three",
+                "Artist: Crowd",
+                "Time: 180",
+                "duration: 180.000",
+                "Pos: 0",
+                "Id: 1",
+                "list_OK",
+                "OK",
+            ]
+        );
+
+        client
+            .send("command_list_begin\nping\ndelete 7\nclear\ncommand_list_end\n")
+            .await;
+        assert_eq!(client.reply().await, ["ACK [2@1] {delete} Bad song index"]);
+        // The list stopped at the failure, the queue was not cleared.
+        client.send("playlistinfo\n").await;
+        assert_eq!(client.reply().await.len(), 8);
+
+        client.send("add \"music/a\n").await;
+        assert_eq!(client.reply().await, ["ACK [2@0] {} Missing closing '\"'"]);
+    }
+
+    #[tokio::test]
+    async fn idle_reports_what_changed_since_the_last_one() {
+        let ctx = context();
+        let events = ctx.events.clone();
+        let mut client = Client::connect(ctx).await;
+
+        // Changes while the client was busy elsewhere are waiting for it.
+        events.emit(Event::QueueChanged { length: 1 });
+        client.send("idle playlist player\n").await;
+        assert_eq!(client.reply().await, ["changed: playlist", "OK"]);
+
+        client.send("idle mixer\n").await;
+        events.emit(Event::VolumeChanged {
+            volume: 50,
+            is_muted: false,
+        });
+        assert_eq!(client.reply().await, ["changed: mixer", "OK"]);
+
+        // Events missed while lagging behind could have been anything, and what one `idle`
+        // doesn't ask for is left for the next.
+        for _ in 0..300 {
+            events.emit(Event::ModesChanged);
+        }
+        client.send("idle mixer options\n").await;
+        assert_eq!(
+            client.reply().await,
+            ["changed: mixer", "changed: options", "OK"]
+        );
+        client.send("idle\n").await;
+        assert_eq!(
+            client.reply().await,
+            [
+                "changed: database",
+                "changed: player",
+                "changed: playlist",
+                "changed: stored_playlist",
+                "OK"
+            ]
+        );
+
+        client.send("idle database\nnoidle\n").await;
+        assert_eq!(client.reply().await, ["OK"]);
+        client.send("ping\n").await;
+        assert_eq!(client.reply().await, ["OK"]);
+    }
+}
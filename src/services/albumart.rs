@@ -8,8 +8,11 @@ use crate::types::State;
 pub async fn albumart(state: web::Data<State>, path: web::Path<Uuid>) -> impl Responder {
     let song_uuid = path.into_inner();
 
-    let index = state.lock().await.index.clone();
-    let Some(songmeta) = index.get(&song_uuid) else {
+    let songmeta = {
+        let state = state.lock().await;
+        state.index.get(&state.resolve(song_uuid)).cloned()
+    };
+    let Some(songmeta) = songmeta else {
         return HttpResponse::NotFound().body("No such song uuid!");
     };
 
@@ -17,7 +20,7 @@ pub async fn albumart(state: web::Data<State>, path: web::Path<Uuid>) -> impl Re
         return HttpResponse::NotFound().body("Could not open file metadata");
     };
     if let Some(tag) = tagged_file.primary_tag() {
-        let picture = tag.pictures().get(0).unwrap();
+        let picture = tag.pictures().first().unwrap();
 
         let mime_str = picture
             .mime_type()
@@ -0,0 +1,13 @@
+use crate::types::*;
+use actix_web::{HttpResponse, Responder, post, web};
+
+#[post("/consume")]
+pub async fn consume(state: web::Data<State>) -> impl Responder {
+    let mut state = state.lock().await;
+    let on = !state.settings.consume;
+    state.set_consume(on).await;
+
+    let message = format!("Consume mode is now {}.", if on { "on" } else { "off" });
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
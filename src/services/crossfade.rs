@@ -0,0 +1,18 @@
+use crate::types::*;
+use actix_web::{HttpResponse, Responder, post, web};
+use std::time::Duration;
+
+#[post("/crossfade/{n}")]
+pub async fn crossfade(state: web::Data<State>, path: web::Path<u64>) -> impl Responder {
+    let n = path.into_inner();
+    let mut state = state.lock().await;
+    state.set_crossfade(Duration::from_secs(n)).await;
+
+    let message = if n == 0 {
+        String::from("Crossfade disabled.")
+    } else {
+        format!("Crossfading {n} sec(s) between songs.")
+    };
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
@@ -2,6 +2,7 @@ use actix_web::{
     HttpResponse, Responder, post,
     web::{self},
 };
+use std::path::Path;
 use uuid::Uuid;
 
 use crate::types::*;
@@ -12,7 +13,7 @@ pub async fn enqueue(state: web::Data<State>, path: web::Path<Uuid>) -> impl Res
 
     let mut state = state.lock().await;
 
-    let song = match state.index.get(&song_uuid) {
+    let song = match state.index.get(&state.resolve(song_uuid)) {
         Some(s) => s.clone(),
         None => {
             return HttpResponse::NotFound().body("No such song with id {song_uuid}");
@@ -21,7 +22,7 @@ pub async fn enqueue(state: web::Data<State>, path: web::Path<Uuid>) -> impl Res
 
     let should_start = state.queue.is_empty();
 
-    state.queue.push(song.clone());
+    state.push_entry(song.clone());
 
     if should_start {
         state.add().await;
@@ -32,3 +33,73 @@ pub async fn enqueue(state: web::Data<State>, path: web::Path<Uuid>) -> impl Res
 
     HttpResponse::Ok().json(Response::Confirm { message })
 }
+
+#[post("/add/next/{uuid}")]
+pub async fn enqueue_next(state: web::Data<State>, path: web::Path<Uuid>) -> impl Responder {
+    let song_uuid = path.into_inner();
+
+    let mut state = state.lock().await;
+
+    let song = match state.index.get(&state.resolve(song_uuid)) {
+        Some(s) => s.clone(),
+        None => {
+            return HttpResponse::NotFound().body(format!("No such song with id {song_uuid}"));
+        }
+    };
+
+    state.insert_next(song.clone());
+
+    let message = format!("Playing {} by {} next.", song.title, song.artists[0]);
+    tracing::info!("{message}");
+
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+/// Appends everything in a directory of a library root and below it, in path order.
+#[post("/add/dir/{root}{path:.*}")]
+pub async fn enqueue_dir(
+    state: web::Data<State>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (root, dir) = path.into_inner();
+    let mut state = state.lock().await;
+
+    let songs = state.songs_under(&root, Path::new(dir.trim_matches('/')));
+    if songs.is_empty() {
+        return HttpResponse::NotFound().body("No songs in that directory");
+    }
+    let count = songs.len();
+    for song in songs {
+        state.push_entry(song);
+    }
+
+    let message = format!("Added {count} songs from {root}{dir} to queue.");
+    tracing::info!("{message}");
+
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+/// Swaps the queue for everything in a directory, which then plays from the top.
+#[post("/replace/dir/{root}{path:.*}")]
+pub async fn replace_dir(
+    state: web::Data<State>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (root, dir) = path.into_inner();
+    let mut state = state.lock().await;
+
+    let songs = state.songs_under(&root, Path::new(dir.trim_matches('/')));
+    if songs.is_empty() {
+        return HttpResponse::NotFound().body("No songs in that directory");
+    }
+    let count = songs.len();
+    state.clear().await;
+    for song in songs {
+        state.push_entry(song);
+    }
+
+    let message = format!("Replaced the queue with {count} songs from {root}{dir}.");
+    tracing::info!("{message}");
+
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
@@ -0,0 +1,74 @@
+use actix_web::{HttpRequest, HttpResponse, Responder, get, web};
+use futures_util::{StreamExt, stream};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::types::*;
+
+/// Quiet streams get a comment this often so proxies don't drop them.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    /// `epoch-version` as in the event IDs, or just a version.
+    since: Option<String>,
+}
+
+fn sse(event: &Versioned) -> web::Bytes {
+    let data = serde_json::to_string(event).unwrap_or_default();
+    web::Bytes::from(format!(
+        "id: {}-{}\ndata: {data}\n\n",
+        event.epoch, event.version
+    ))
+}
+
+/// Server-sent events for every change to the player. A client that reconnects with
+/// `Last-Event-ID` or `?since=` first gets what it missed, as far back as it is remembered.
+/// If the daemon restarted in between, it gets a `resync` event instead.
+#[get("/events")]
+pub async fn events(
+    events: web::Data<Events>,
+    req: HttpRequest,
+    query: web::Query<EventsQuery>,
+) -> impl Responder {
+    let since = match query.into_inner().since {
+        Some(since) => match Since::parse(&since) {
+            Some(since) => Some(since),
+            None => return HttpResponse::NotFound().body(format!("Invalid since {since}")),
+        },
+        None => req
+            .headers()
+            .get("Last-Event-ID")
+            .and_then(|v| v.to_str().ok())
+            .and_then(Since::parse),
+    };
+    let (missed, receiver) = events.subscribe(since);
+    tracing::info!("Event stream opened.");
+
+    let missed = missed.unwrap_or_else(|| {
+        vec![Versioned {
+            epoch: events.epoch(),
+            version: events.version(),
+            event: Event::Resync,
+        }]
+    });
+    let missed = stream::iter(missed).map(|event| Ok::<_, actix_web::Error>(sse(&event)));
+    let live = stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            let bytes = match tokio::time::timeout(KEEPALIVE, receiver.recv()).await {
+                Ok(Ok(event)) => sse(&event),
+                // Too slow to keep up, the gap in versions tells the client.
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) => return None,
+                Err(_) => web::Bytes::from_static(b": keepalive\n\n"),
+            };
+            return Some((Ok(bytes), receiver));
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(missed.chain(live))
+}
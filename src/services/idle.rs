@@ -0,0 +1,93 @@
+use actix_web::{HttpResponse, Responder, get, web};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::time::{Instant, timeout_at};
+
+use crate::types::*;
+
+const DEFAULT_TIMEOUT: u64 = 30;
+const MAX_TIMEOUT: u64 = 300;
+
+#[derive(Deserialize)]
+pub struct IdleQuery {
+    /// Comma separated, every subsystem if not given.
+    subsystems: Option<String>,
+    /// Seconds to wait at most.
+    timeout: Option<u64>,
+    /// Also count changes after this version that happened before the request came in.
+    /// Needs `epoch` from an earlier answer to tell if the daemon restarted since.
+    since: Option<u64>,
+    epoch: Option<u64>,
+}
+
+/// Long-polls for changes. Only waits on the event stream, the player is never locked.
+#[get("/idle")]
+pub async fn idle(events: web::Data<Events>, query: web::Query<IdleQuery>) -> impl Responder {
+    let query = query.into_inner();
+    let mut wanted = BTreeSet::new();
+    for name in query.subsystems.iter().flat_map(|s| s.split(',')) {
+        match Subsystem::parse(name.trim()) {
+            Some(subsystem) => {
+                wanted.insert(subsystem);
+            }
+            None => return HttpResponse::NotFound().body(format!("Invalid subsystem {name}")),
+        }
+    }
+    let is_wanted = |event: &Versioned| {
+        event
+            .event
+            .subsystem()
+            .filter(|s| wanted.is_empty() || wanted.contains(s))
+    };
+
+    let deadline = Instant::now()
+        + Duration::from_secs(query.timeout.unwrap_or(DEFAULT_TIMEOUT).min(MAX_TIMEOUT));
+    let since = query.since.map(|version| Since {
+        epoch: query.epoch,
+        version,
+    });
+    let (missed, mut receiver) = events.subscribe(since);
+    let mut version = events.version();
+    // When it is not known what changed, anything may have.
+    let everything: BTreeSet<Subsystem> = if wanted.is_empty() {
+        Subsystem::ALL.into()
+    } else {
+        wanted.clone()
+    };
+    let mut changed: BTreeSet<Subsystem> = match missed {
+        Some(missed) => missed.iter().filter_map(is_wanted).collect(),
+        // A version from before a restart.
+        None => everything.clone(),
+    };
+
+    while changed.is_empty() {
+        match timeout_at(deadline, receiver.recv()).await {
+            Ok(Ok(event)) => {
+                version = event.version;
+                changed.extend(is_wanted(&event));
+            }
+            // Events were dropped before we got to them.
+            Ok(Err(RecvError::Lagged(_))) => changed.clone_from(&everything),
+            Ok(Err(RecvError::Closed)) | Err(_) => break,
+        }
+    }
+    // Whatever else came in the same breath.
+    loop {
+        match receiver.try_recv() {
+            Ok(event) => {
+                version = event.version;
+                changed.extend(is_wanted(&event));
+            }
+            Err(TryRecvError::Lagged(_)) => changed.clone_from(&everything),
+            Err(_) => break,
+        }
+    }
+
+    HttpResponse::Ok().json(Response::Idle {
+        changed: changed.into_iter().collect(),
+        epoch: events.epoch(),
+        version,
+    })
+}
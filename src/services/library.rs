@@ -0,0 +1,106 @@
+use actix_web::{HttpResponse, Responder, get, post, web};
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::{helpers, types::*};
+
+#[get("/library/roots")]
+pub async fn library_roots(state: web::Data<State>) -> impl Responder {
+    let state = state.lock().await;
+    let roots = state
+        .library
+        .roots()
+        .map(|root| {
+            let name = root.name();
+            RootInfo {
+                songs: state.index.values().filter(|s| s.root == name).count(),
+                name,
+                path: root.path.clone(),
+            }
+        })
+        .collect();
+    HttpResponse::Ok().json(Response::Roots(roots))
+}
+
+/// Songs in one root sorted by path.
+#[get("/library/roots/{name}")]
+pub async fn library_root(state: web::Data<State>, path: web::Path<String>) -> impl Responder {
+    let name = path.into_inner();
+    let state = state.lock().await;
+    if state.library.root(&name).is_none() {
+        return HttpResponse::NotFound().body("Invalid library root");
+    }
+    let mut songs: Vec<&SongMeta> = state.index.values().filter(|s| s.root == name).collect();
+    songs.sort_by(|a, b| a.file.cmp(&b.file));
+    HttpResponse::Ok().json(Response::Songs(songs.into_iter().map(Song::from).collect()))
+}
+
+#[get("/library/scan")]
+pub async fn library_scan_status(state: web::Data<State>) -> impl Responder {
+    let state = state.lock().await;
+    HttpResponse::Ok().json(Response::Scan(state.scan.clone()))
+}
+
+/// Rescans every root in the background.
+#[post("/library/scan")]
+pub async fn library_scan(state: web::Data<State>) -> impl Responder {
+    let roots = {
+        let state = state.lock().await;
+        if state.scan.as_ref().is_some_and(|scan| scan.running) {
+            return HttpResponse::Conflict().body("A library scan is already running");
+        }
+        state.library.paths()
+    };
+    tokio::spawn(helpers::rescan(state.clone(), roots));
+
+    let message = "Started a library scan.".to_string();
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+#[get("/library/artists")]
+pub async fn library_artists(state: web::Data<State>) -> impl Responder {
+    let state = state.lock().await;
+    HttpResponse::Ok().json(Response::Artists(state.artists()))
+}
+
+#[get("/library/artists/{id}/albums")]
+pub async fn library_artist_albums(
+    state: web::Data<State>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let state = state.lock().await;
+    match state.artist_albums(path.into_inner()) {
+        Some(albums) => HttpResponse::Ok().json(Response::Albums(albums)),
+        None => HttpResponse::NotFound().body("No such artist"),
+    }
+}
+
+#[get("/library/albums/{id}")]
+pub async fn library_album(state: web::Data<State>, path: web::Path<Uuid>) -> impl Responder {
+    let state = state.lock().await;
+    match state.album(path.into_inner()) {
+        Some(album) => HttpResponse::Ok().json(Response::Album(album)),
+        None => HttpResponse::NotFound().body("No such album"),
+    }
+}
+
+#[get("/library/genres")]
+pub async fn library_genres(state: web::Data<State>) -> impl Responder {
+    let state = state.lock().await;
+    HttpResponse::Ok().json(Response::Genres(state.genres()))
+}
+
+/// Like MPD's `lsinfo`, `path` is relative to the root and may be empty.
+#[get("/library/browse/{root}{path:.*}")]
+pub async fn library_browse(
+    state: web::Data<State>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (root, dir) = path.into_inner();
+    let state = state.lock().await;
+    match state.list_dir(&root, Path::new(dir.trim_matches('/'))) {
+        Some(listing) => HttpResponse::Ok().json(Response::Directory(listing)),
+        None => HttpResponse::NotFound().body("No such directory"),
+    }
+}
@@ -1,18 +1,38 @@
 mod albumart;
 mod clear;
+mod consume;
+mod crossfade;
 mod enqueue;
+mod events;
+mod idle;
+mod library;
 mod next_prev;
 mod pause;
 mod playlist;
+mod queue;
+mod repeat;
+mod replaygain;
 mod search;
 mod seek;
+mod shuffle;
 mod status;
+mod volume;
 pub use albumart::*;
 pub use clear::*;
+pub use consume::*;
+pub use crossfade::*;
 pub use enqueue::*;
+pub use events::*;
+pub use idle::*;
+pub use library::*;
 pub use next_prev::*;
 pub use pause::*;
 pub use playlist::*;
+pub use queue::*;
+pub use repeat::*;
+pub use replaygain::*;
 pub use search::*;
 pub use seek::*;
+pub use shuffle::*;
 pub use status::*;
+pub use volume::*;
@@ -7,9 +7,15 @@ use crate::{
 };
 
 #[post("/playlist/create")]
-pub async fn playlist_create(item: web::Json<PlaylistIn>) -> impl Responder {
+pub async fn playlist_create(
+    events: web::Data<Events>,
+    item: web::Json<PlaylistIn>,
+) -> impl Responder {
     match create_playlist(item.into_inner()).await {
         Ok(name) => {
+            events.emit(Event::PlaylistChanged {
+                title: name.clone(),
+            });
             let message = format!("Created playlist {name}.");
             HttpResponse::Ok().json(Response::Confirm { message })
         }
@@ -32,10 +38,17 @@ pub async fn playlist_list() -> impl Responder {
 }
 
 #[get("/playlist/load/{id}")]
-pub async fn playlist_get(path: web::Path<Uuid>) -> impl Responder {
+pub async fn playlist_get(state: web::Data<State>, path: web::Path<Uuid>) -> impl Responder {
     let id = path.into_inner();
     match get_playlist(id).await {
-        Ok(playlistmeta) => HttpResponse::Ok().json(playlistmeta),
+        Ok(mut playlistmeta) => {
+            let state = state.lock().await;
+            for song in &mut playlistmeta.songs {
+                song.id = state.resolve(song.id);
+                song.missing = !state.index.contains_key(&song.id);
+            }
+            HttpResponse::Ok().json(playlistmeta)
+        }
         Err(err) => HttpResponse::NotFound().json(Response::Error {
             err_id: 4,
             err_msg: err.to_string(),
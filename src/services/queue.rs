@@ -0,0 +1,128 @@
+use crate::types::*;
+use actix_web::{HttpResponse, Responder, post, web};
+
+#[post("/queue/remove/{pos}")]
+pub async fn queue_remove(state: web::Data<State>, path: web::Path<usize>) -> impl Responder {
+    let pos = path.into_inner();
+    let mut state = state.lock().await;
+    if !state.remove_range(pos, pos + 1).await {
+        return HttpResponse::NotFound().body("Invalid queue position");
+    }
+
+    let message = format!("Removed entry {pos} from the queue.");
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+#[post("/queue/remove/{start}/{end}")]
+pub async fn queue_remove_range(
+    state: web::Data<State>,
+    path: web::Path<(usize, usize)>,
+) -> impl Responder {
+    let (start, end) = path.into_inner();
+    let mut state = state.lock().await;
+    if !state.remove_range(start, end).await {
+        return HttpResponse::NotFound().body("Invalid queue range");
+    }
+
+    let message = format!("Removed entries {start} to {end} from the queue.");
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+#[post("/queue/move/{from}/{to}")]
+pub async fn queue_move(
+    state: web::Data<State>,
+    path: web::Path<(usize, usize)>,
+) -> impl Responder {
+    let (from, to) = path.into_inner();
+    let mut state = state.lock().await;
+    if !state.move_range(from, from + 1, to) {
+        return HttpResponse::NotFound().body("Invalid queue position");
+    }
+
+    let message = format!("Moved entry {from} to {to}.");
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+#[post("/queue/move/{start}/{end}/{to}")]
+pub async fn queue_move_range(
+    state: web::Data<State>,
+    path: web::Path<(usize, usize, usize)>,
+) -> impl Responder {
+    let (start, end, to) = path.into_inner();
+    let mut state = state.lock().await;
+    if !state.move_range(start, end, to) {
+        return HttpResponse::NotFound().body("Invalid queue range");
+    }
+
+    let message = format!("Moved entries {start} to {end} to {to}.");
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+#[post("/play/{pos}")]
+pub async fn play(state: web::Data<State>, path: web::Path<usize>) -> impl Responder {
+    let pos = path.into_inner();
+    let mut state = state.lock().await;
+    if pos >= state.queue.len() {
+        return HttpResponse::NotFound().body("Invalid queue position");
+    }
+    if let GetReturn::Ok = state.jump(pos).await {
+        state.add().await;
+    }
+
+    let message = format!("Playing entry {pos}.");
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+#[post("/queue/id/{id}/remove")]
+pub async fn queue_remove_id(state: web::Data<State>, path: web::Path<u64>) -> impl Responder {
+    let id = path.into_inner();
+    let mut state = state.lock().await;
+    let Some(pos) = state.entry_pos(id) else {
+        return HttpResponse::NotFound().body("No such queue entry");
+    };
+    state.remove_range(pos, pos + 1).await;
+
+    let message = format!("Removed entry {id} from the queue.");
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+#[post("/queue/id/{id}/move/{to}")]
+pub async fn queue_move_id(
+    state: web::Data<State>,
+    path: web::Path<(u64, usize)>,
+) -> impl Responder {
+    let (id, to) = path.into_inner();
+    let mut state = state.lock().await;
+    let Some(pos) = state.entry_pos(id) else {
+        return HttpResponse::NotFound().body("No such queue entry");
+    };
+    if !state.move_range(pos, pos + 1, to) {
+        return HttpResponse::NotFound().body("Invalid queue position");
+    }
+
+    let message = format!("Moved entry {id} to {to}.");
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+#[post("/queue/id/{id}/play")]
+pub async fn play_id(state: web::Data<State>, path: web::Path<u64>) -> impl Responder {
+    let id = path.into_inner();
+    let mut state = state.lock().await;
+    let Some(pos) = state.entry_pos(id) else {
+        return HttpResponse::NotFound().body("No such queue entry");
+    };
+    if let GetReturn::Ok = state.jump(pos).await {
+        state.add().await;
+    }
+
+    let message = format!("Playing entry {id}.");
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
@@ -0,0 +1,32 @@
+use crate::types::*;
+use actix_web::{HttpResponse, Responder, post, web};
+
+#[post("/repeat/{mode}")]
+pub async fn repeat(state: web::Data<State>, path: web::Path<String>) -> impl Responder {
+    let mode = path.into_inner();
+    let repeat_mode = match mode.as_str() {
+        "off" => RepeatMode::Off,
+        "all" => RepeatMode::All,
+        "one" => RepeatMode::One,
+        _ => {
+            return HttpResponse::NotFound()
+                .body("Invalid repeat mode, must be `off`, `all` or `one`");
+        }
+    };
+    state.lock().await.set_repeat(repeat_mode).await;
+
+    let message = format!("Repeat mode set to {mode}.");
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+#[post("/single")]
+pub async fn single(state: web::Data<State>) -> impl Responder {
+    let mut state = state.lock().await;
+    let on = !state.settings.single;
+    state.set_single(on).await;
+
+    let message = format!("Single mode is now {}.", if on { "on" } else { "off" });
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
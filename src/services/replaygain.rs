@@ -0,0 +1,22 @@
+use crate::types::*;
+use actix_web::{HttpResponse, Responder, post, web};
+
+#[post("/replaygain/{mode}")]
+pub async fn replaygain(state: web::Data<State>, path: web::Path<String>) -> impl Responder {
+    let mode = path.into_inner();
+    let replaygain_mode = match mode.as_str() {
+        "off" => ReplayGainMode::Off,
+        "track" => ReplayGainMode::Track,
+        "album" => ReplayGainMode::Album,
+        "auto" => ReplayGainMode::Auto,
+        _ => {
+            return HttpResponse::NotFound()
+                .body("Invalid replaygain mode, must be `off`, `track`, `album` or `auto`");
+        }
+    };
+    state.lock().await.set_replaygain(replaygain_mode).await;
+
+    let message = format!("ReplayGain mode set to {mode}.");
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
@@ -18,7 +18,7 @@ pub async fn search(state: web::Data<State>, path: web::Path<(String, String)>)
         .search(searchtype)
         .await
         .iter()
-        .map(|songmeta| Song::from(songmeta))
+        .map(Song::from)
         .collect();
     HttpResponse::Ok().json(Response::SearchResults(songs))
 }
@@ -0,0 +1,13 @@
+use crate::types::*;
+use actix_web::{HttpResponse, Responder, post, web};
+
+#[post("/shuffle")]
+pub async fn shuffle(state: web::Data<State>) -> impl Responder {
+    let mut state = state.lock().await;
+    let on = state.shuffle.is_none();
+    state.set_shuffle(on).await;
+
+    let message = format!("Shuffle is now {}.", if on { "on" } else { "off" });
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
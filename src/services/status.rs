@@ -6,5 +6,5 @@ use crate::types::*;
 #[get("/")]
 pub async fn status(s: web::Data<Mutex<StateStruct>>) -> impl Responder {
     let state = s.lock().await;
-    HttpResponse::Ok().json(Response::Status(state.to_status()))
+    HttpResponse::Ok().json(Response::Status(Box::new(state.to_status())))
 }
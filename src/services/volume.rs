@@ -0,0 +1,51 @@
+use actix_web::{HttpResponse, Responder, get, post, web};
+
+use crate::types::*;
+
+#[get("/volume")]
+pub async fn volume_get(state: web::Data<State>) -> impl Responder {
+    let state = state.lock().await;
+    HttpResponse::Ok().json(Response::Volume {
+        volume: state.settings.volume,
+        is_muted: state.settings.muted,
+    })
+}
+
+#[post("/volume/mute")]
+pub async fn volume_mute(state: web::Data<State>) -> impl Responder {
+    state.lock().await.set_muted(true).await;
+    let message = String::from("Muted.");
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+#[post("/volume/unmute")]
+pub async fn volume_unmute(state: web::Data<State>) -> impl Responder {
+    let mut state = state.lock().await;
+    state.set_muted(false).await;
+    let message = format!("Unmuted, volume is {}%.", state.settings.volume);
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
+
+/// `{change}` is an absolute percentage like `40`, or relative like `+5` or `-10`.
+#[post("/volume/{change}")]
+pub async fn volume_set(state: web::Data<State>, path: web::Path<String>) -> impl Responder {
+    let change = path.into_inner();
+    let Ok(amount) = change.parse::<i32>() else {
+        return HttpResponse::NotFound()
+            .body("Invalid volume, must be a percentage like `40`, `+5` or `-10`");
+    };
+
+    let mut state = state.lock().await;
+    let volume = if change.starts_with('+') || change.starts_with('-') {
+        state.settings.volume as i32 + amount
+    } else {
+        amount
+    };
+    state.set_volume(volume).await;
+
+    let message = format!("Volume set to {}%.", state.settings.volume);
+    tracing::info!("{message}");
+    HttpResponse::Ok().json(Response::Confirm { message })
+}
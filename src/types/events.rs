@@ -0,0 +1,265 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::types::Song;
+
+/// Events kept around for clients that reconnect and ask for what they missed.
+const HISTORY: usize = 256;
+
+/// Something about the player that clients may want to redraw.
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    SongChanged {
+        current_song: Option<Box<Song>>,
+        current_idx: usize,
+    },
+    /// Sent every second while playing, and on seeks.
+    Position {
+        position: Duration,
+    },
+    PauseChanged {
+        is_paused: bool,
+    },
+    /// Entries were added, removed or moved, fetch `/` for the new queue.
+    QueueChanged {
+        length: usize,
+    },
+    PlaylistChanged {
+        title: String,
+    },
+    /// A rescan finished. Loudness measured in the background is not announced, clients
+    /// never see it.
+    IndexRescanned {
+        songs: usize,
+    },
+    VolumeChanged {
+        volume: u8,
+        is_muted: bool,
+    },
+    /// Shuffle, repeat, single or consume was switched.
+    ModesChanged,
+    /// Only sent to a client that asked for changes since a version this run of the daemon
+    /// never had, it has to fetch everything again.
+    Resync,
+}
+
+/// Coarse areas of change that idle waiters subscribe to.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Subsystem {
+    Player,
+    Queue,
+    /// Stored playlists.
+    Playlist,
+    Database,
+    Mixer,
+    Options,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 6] = [
+        Subsystem::Player,
+        Subsystem::Queue,
+        Subsystem::Playlist,
+        Subsystem::Database,
+        Subsystem::Mixer,
+        Subsystem::Options,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "player" => Some(Subsystem::Player),
+            "queue" => Some(Subsystem::Queue),
+            "playlist" => Some(Subsystem::Playlist),
+            "database" => Some(Subsystem::Database),
+            "mixer" => Some(Subsystem::Mixer),
+            "options" => Some(Subsystem::Options),
+            _ => None,
+        }
+    }
+
+    /// What MPD calls it, which differs for the queue and stored playlists.
+    pub fn mpd_name(self) -> &'static str {
+        match self {
+            Subsystem::Player => "player",
+            Subsystem::Queue => "playlist",
+            Subsystem::Playlist => "stored_playlist",
+            Subsystem::Database => "database",
+            Subsystem::Mixer => "mixer",
+            Subsystem::Options => "options",
+        }
+    }
+}
+
+impl Event {
+    /// The subsystem the event belongs to. Position ticks belong to none, idle waiters work
+    /// the position out themselves.
+    pub fn subsystem(&self) -> Option<Subsystem> {
+        match self {
+            Event::SongChanged { .. } | Event::PauseChanged { .. } => Some(Subsystem::Player),
+            Event::Position { .. } | Event::Resync => None,
+            Event::QueueChanged { .. } => Some(Subsystem::Queue),
+            Event::PlaylistChanged { .. } => Some(Subsystem::Playlist),
+            Event::IndexRescanned { .. } => Some(Subsystem::Database),
+            Event::VolumeChanged { .. } => Some(Subsystem::Mixer),
+            Event::ModesChanged => Some(Subsystem::Options),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct Versioned {
+    /// Picked at random when the daemon starts, versions from another epoch mean nothing.
+    pub epoch: u64,
+    pub version: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Where a client left off, as `epoch-version` or just `version`.
+#[derive(Clone, Copy)]
+pub struct Since {
+    pub epoch: Option<u64>,
+    pub version: u64,
+}
+
+impl Since {
+    pub fn parse(since: &str) -> Option<Self> {
+        match since.split_once('-') {
+            Some((epoch, version)) => Some(Since {
+                epoch: Some(epoch.parse().ok()?),
+                version: version.parse().ok()?,
+            }),
+            None => Some(Since {
+                epoch: None,
+                version: since.parse().ok()?,
+            }),
+        }
+    }
+}
+
+/// Fans events out to every listener, numbering them as they go. Lives outside the `State`
+/// mutex, so waiting for an event never holds up the player. Versions start over with every
+/// run of the daemon, the epoch tells runs apart.
+pub struct Events {
+    epoch: u64,
+    sender: broadcast::Sender<Versioned>,
+    history: Mutex<VecDeque<Versioned>>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self {
+            epoch: rand::random(),
+            sender: broadcast::channel(HISTORY).0,
+            history: Mutex::new(VecDeque::with_capacity(HISTORY)),
+        }
+    }
+}
+
+impl Events {
+    pub fn emit(&self, event: Event) {
+        // Numbered and sent under the lock, so listeners see versions in order.
+        let mut history = self.history.lock().unwrap();
+        let version = history.back().map_or(1, |last| last.version + 1);
+        let versioned = Versioned {
+            epoch: self.epoch,
+            version,
+            event,
+        };
+        if history.len() == HISTORY {
+            history.pop_front();
+        }
+        history.push_back(versioned.clone());
+        // Nobody listening is fine.
+        let _ = self.sender.send(versioned);
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Version of the newest event, 0 before the first.
+    pub fn version(&self) -> u64 {
+        self.history
+            .lock()
+            .unwrap()
+            .back()
+            .map_or(0, |last| last.version)
+    }
+
+    /// Starts listening, with everything after `since` that is still remembered. Listeners
+    /// can tell something was lost from a gap in the versions. The missed events are `None`
+    /// if `since` is from another epoch or ahead of the newest version, the listener has to
+    /// resync then.
+    pub fn subscribe(
+        &self,
+        since: Option<Since>,
+    ) -> (Option<Vec<Versioned>>, broadcast::Receiver<Versioned>) {
+        let history = self.history.lock().unwrap();
+        let missed = match since {
+            None => Some(Vec::new()),
+            Some(since)
+                if since.epoch.is_some_and(|epoch| epoch != self.epoch)
+                    || since.version > history.back().map_or(0, |last| last.version) =>
+            {
+                None
+            }
+            Some(since) => Some(
+                history
+                    .iter()
+                    .filter(|event| event.version > since.version)
+                    .cloned()
+                    .collect(),
+            ),
+        };
+        (missed, self.sender.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn since(epoch: Option<u64>, version: u64) -> Option<Since> {
+        Some(Since { epoch, version })
+    }
+
+    #[test]
+    fn subscribe_replays_what_was_missed() {
+        let events = Events::default();
+        for _ in 0..3 {
+            events.emit(Event::ModesChanged);
+        }
+        let (missed, _) = events.subscribe(since(Some(events.epoch()), 1));
+        let versions: Vec<u64> = missed.unwrap().iter().map(|e| e.version).collect();
+        assert_eq!(versions, [2, 3]);
+
+        let (missed, _) = events.subscribe(None);
+        assert!(missed.unwrap().is_empty());
+    }
+
+    #[test]
+    fn subscribe_asks_for_a_resync_across_restarts() {
+        let events = Events::default();
+        events.emit(Event::ModesChanged);
+
+        let (missed, _) = events.subscribe(since(Some(events.epoch().wrapping_add(1)), 0));
+        assert!(missed.is_none());
+        let (missed, _) = events.subscribe(since(None, 5));
+        assert!(missed.is_none());
+    }
+
+    #[test]
+    fn since_parses_with_and_without_epoch() {
+        let since = Since::parse("12-34").unwrap();
+        assert_eq!((since.epoch, since.version), (Some(12), 34));
+        let since = Since::parse("34").unwrap();
+        assert_eq!((since.epoch, since.version), (None, 34));
+        assert!(Since::parse("x-1").is_none());
+    }
+}
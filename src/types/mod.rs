@@ -1,8 +1,14 @@
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf, time::Duration};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
+mod events;
+pub use events::*;
 mod response_types;
 pub use response_types::*;
 mod state_impl;
@@ -10,19 +16,285 @@ pub use state_impl::*;
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct SongMeta {
+    /// Derived from the audio, so it survives renames and retagging.
     pub id: Uuid,
     pub title: String,
     pub artists: Vec<String>,
     pub duration: Duration,
     pub path: PathBuf,
+    /// Name of the library root the file is under, and its path relative to that root.
+    #[serde(default)]
+    pub root: String,
+    #[serde(default)]
+    pub file: PathBuf,
+    #[serde(default)]
+    pub tags: Tags,
+    #[serde(default)]
+    pub replaygain: ReplayGain,
+    /// What the file looked like when it was probed. Rescans only probe it again if this
+    /// changed.
+    #[serde(default)]
+    pub stamp: Option<FileStamp>,
+}
+
+/// Tags beyond title and artists, whatever the file has of them.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Tags {
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc: Option<u32>,
+    pub disc_total: Option<u32>,
+    /// As tagged, a year or a full date.
+    pub date: Option<String>,
+    pub year: Option<u32>,
+    pub genres: Vec<String>,
+    pub composer: Option<String>,
+    pub comment: Option<String>,
+    pub musicbrainz: MusicBrainzIds,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MusicBrainzIds {
+    pub recording: Option<String>,
+    pub track: Option<String>,
+    pub release: Option<String>,
+    pub release_group: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+}
+
+impl SongMeta {
+    /// `root/file`, how clients that see the whole library as one tree name the song.
+    pub fn uri(&self) -> String {
+        Path::new(&self.root)
+            .join(&self.file)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+#[cfg(test)]
+impl SongMeta {
+    /// A song at `/music/<file>` with an ID made up from the path.
+    pub fn for_tests(file: &str, title: &str, artist: &str) -> Self {
+        SongMeta {
+            id: Uuid::new_v5(&Uuid::NAMESPACE_URL, file.as_bytes()),
+            title: title.to_string(),
+            artists: vec![artist.to_string()],
+            duration: Duration::from_secs(180),
+            path: Path::new("/music").join(file),
+            root: "music".to_string(),
+            file: file.into(),
+            tags: Tags::default(),
+            replaygain: ReplayGain::default(),
+            stamp: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct FileStamp {
+    pub mtime: SystemTime,
+    pub size: u64,
+}
+
+/// How far the running library rescan has come, or how the last one ended.
+#[derive(Clone, Default, Serialize)]
+pub struct ScanProgress {
+    pub running: bool,
+    /// Audio files under the library roots.
+    pub seen: usize,
+    /// Files left alone since they didn't change since the last scan.
+    pub skipped: usize,
+    /// New or changed files that need probing, and how they went so far.
+    pub to_probe: usize,
+    pub probed: usize,
+    pub failed: usize,
+    /// Indexed songs whose file is gone.
+    pub removed: usize,
+    /// Time left for probing, going by how long it took so far.
+    pub eta: Option<Duration>,
+}
+
+/// A song in the queue. `id` tells apart several entries for the same song and stays the
+/// same while other entries come and go, like MPD's songid.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct QueueEntry {
+    pub id: u64,
+    pub song: SongMeta,
+}
+
+/// ReplayGain values in dB (gains) and linear full scale (peaks), read from the tags or
+/// measured by `helpers::analyze_loudness` when the file has none.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+    /// `helpers::analyze_loudness` ran on the file, even if it came up with nothing, so it
+    /// is not tried again.
+    #[serde(default)]
+    pub measured: bool,
+}
+
+impl ReplayGain {
+    /// Linear factor to play the song at under `mode`, held back so the peak doesn't clip.
+    pub fn factor(&self, mode: ReplayGainMode) -> f32 {
+        let (gain, peak) = match mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track => (
+                self.track_gain.or(self.album_gain),
+                self.track_peak.or(self.album_peak),
+            ),
+            ReplayGainMode::Album | ReplayGainMode::Auto => (
+                self.album_gain.or(self.track_gain),
+                self.album_peak.or(self.track_peak),
+            ),
+        };
+        let Some(gain) = gain else {
+            return 1.0;
+        };
+        let factor = 10f32.powf(gain / 20.0);
+        match peak {
+            Some(peak) if peak > 0.0 => factor.min(1.0 / peak),
+            _ => factor,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayGainMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+    /// Album gain where the song has it, track gain otherwise. Always track gain while
+    /// shuffling.
+    Auto,
+}
+
+/// Sample rate and channel count audio is played at. Songs in any other format are
+/// converted to it on their decoder thread.
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct OutputFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+/// What happens when playback reaches the end of the queue, or of a song for `One`.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatMode {
+    /// Stop after the last song.
+    Off,
+    /// Start over from the top.
+    #[default]
+    All,
+    /// Loop the current song. Skipping still moves on.
+    One,
+}
+
+/// The queue and where playback was in it, saved so a restart picks up where the last run
+/// left off. Modes live in `Settings`.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Session {
+    pub queue: Vec<QueueEntry>,
+    pub current_idx: usize,
+    /// Whether a song was current, `current_idx` alone can't tell.
+    pub has_current: bool,
+    pub position: Duration,
+    pub paused: bool,
+    pub stopped: bool,
+    pub next_entry_id: u64,
 }
 
 pub type SongIndex = HashMap<Uuid, SongMeta>;
+/// Song IDs that went away, pointing to the ID the same song has now.
+pub type SongAliases = HashMap<Uuid, Uuid>;
 pub type State = Mutex<StateStruct>;
 
+/// A directory songs are indexed from.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct LibraryRoot {
+    pub path: PathBuf,
+    /// What the root is called in song paths and browsing, the directory's own name if not
+    /// set. Should be unique.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Globs of paths to leave out, relative to the root, like `*/Samples/*`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl LibraryRoot {
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            self.path.file_name().map_or_else(
+                || "library".to_string(),
+                |n| n.to_string_lossy().into_owned(),
+            )
+        })
+    }
+}
+
+/// Player settings that survive daemon restarts.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    pub crossfade: Duration,
+    pub replaygain: ReplayGainMode,
+    /// Percent, kept while muted so unmuting goes back to it.
+    pub volume: u8,
+    pub muted: bool,
+    pub shuffle: bool,
+    pub repeat: RepeatMode,
+    /// Stop after the current song.
+    pub single: bool,
+    /// Take songs out of the queue once they played or were skipped.
+    pub consume: bool,
+    /// Format to open the output device in, the device's own default if not set. Only read
+    /// at startup.
+    pub output_format: Option<OutputFormat>,
+    /// Port for the MPD protocol listener, off if not set. Only read at startup.
+    pub mpd_port: Option<u16>,
+    /// Where the music is, `~/Music` by default. Only read at startup.
+    pub library: Vec<LibraryRoot>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            crossfade: Duration::ZERO,
+            replaygain: ReplayGainMode::Off,
+            volume: 100,
+            muted: false,
+            shuffle: false,
+            repeat: RepeatMode::All,
+            single: false,
+            consume: false,
+            output_format: None,
+            mpd_port: None,
+            library: vec![LibraryRoot {
+                path: crate::helpers::music_dir(),
+                name: None,
+                exclude: Vec::new(),
+            }],
+        }
+    }
+}
+
 pub enum GetReturn {
     Ok,
     QueueEmpty,
+    /// Playback stopped, at the end of the queue or in single mode.
+    End,
 }
 
 pub enum SearchType {
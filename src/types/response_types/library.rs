@@ -0,0 +1,50 @@
+use serde::Serialize;
+use std::{path::PathBuf, time::Duration};
+use uuid::Uuid;
+
+use crate::types::Song;
+
+#[derive(Serialize, Clone)]
+pub struct ArtistInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub albums: usize,
+    pub songs: usize,
+    pub duration: Duration,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AlbumInfo {
+    pub id: Uuid,
+    pub title: String,
+    /// The album artist, or the artist of the first track if the files don't name one.
+    pub artist: String,
+    pub year: Option<u32>,
+    pub songs: usize,
+    pub duration: Duration,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AlbumDetails {
+    #[serde(flatten)]
+    pub info: AlbumInfo,
+    /// In disc and track order.
+    pub tracks: Vec<Song>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct GenreInfo {
+    pub name: String,
+    pub albums: usize,
+    pub songs: usize,
+    pub duration: Duration,
+}
+
+/// What is in one directory of a library root. Paths are relative to the root.
+#[derive(Serialize, Clone)]
+pub struct DirListing {
+    pub root: String,
+    pub path: PathBuf,
+    pub directories: Vec<PathBuf>,
+    pub songs: Vec<Song>,
+}
@@ -1,26 +1,74 @@
-use std::time::Duration;
+use std::{path::PathBuf, time::Duration};
 
 use serde::Serialize;
 
+use crate::types::{RepeatMode, ReplayGainMode, ScanProgress, Subsystem};
+
+mod library;
 mod playlist;
 mod song;
 
+pub use library::*;
 pub use playlist::*;
 pub use song::*;
 
 #[derive(Serialize)]
 pub struct Status {
     pub current_song: Option<Song>,
-    pub queue: Vec<Song>,
+    pub queue: Vec<QueueSong>,
     pub current_idx: usize,
     pub is_paused: bool,
     pub position: Duration,
+    pub crossfade: Duration,
+    pub replaygain: ReplayGainMode,
+    pub volume: u8,
+    pub is_muted: bool,
+    pub shuffle: bool,
+    pub repeat: RepeatMode,
+    pub single: bool,
+    pub consume: bool,
+    pub is_stopped: bool,
+    /// Progress of the library rescan, `None` when none is running.
+    pub scan: Option<ScanProgress>,
+}
+
+/// A library root as listed by `/library/roots`.
+#[derive(Serialize)]
+pub struct RootInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub songs: usize,
 }
 
 #[derive(Serialize)]
 pub enum Response {
-    Error { err_id: u8, err_msg: String },
-    Status(Status),
+    Error {
+        err_id: u8,
+        err_msg: String,
+    },
+    Status(Box<Status>),
     SearchResults(Vec<Song>),
-    Confirm { message: String },
+    Roots(Vec<RootInfo>),
+    Songs(Vec<Song>),
+    /// The running or last library rescan, `None` if there was none yet.
+    Scan(Option<ScanProgress>),
+    Artists(Vec<ArtistInfo>),
+    Albums(Vec<AlbumInfo>),
+    Album(AlbumDetails),
+    Genres(Vec<GenreInfo>),
+    Directory(DirListing),
+    Volume {
+        volume: u8,
+        is_muted: bool,
+    },
+    /// What changed while waiting in `/idle`, nothing if it timed out. Pass `version` back
+    /// as `since` to not miss changes between polls.
+    Idle {
+        changed: Vec<Subsystem>,
+        epoch: u64,
+        version: u64,
+    },
+    Confirm {
+        message: String,
+    },
 }
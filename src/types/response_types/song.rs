@@ -1,6 +1,6 @@
-use crate::types::SongMeta;
+use crate::types::{QueueEntry, SongMeta, Tags};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::{path::PathBuf, time::Duration};
 use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -9,6 +9,16 @@ pub struct Song {
     pub title: String,
     pub artists: Vec<String>,
     pub duration: Duration,
+    /// Library root the song is in and its path relative to that root.
+    #[serde(default)]
+    pub root: String,
+    #[serde(default)]
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub tags: Tags,
+    /// Set on playlist entries whose file is no longer in the library.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub missing: bool,
 }
 
 impl From<&SongMeta> for Song {
@@ -18,6 +28,27 @@ impl From<&SongMeta> for Song {
             title: value.title.clone(),
             artists: value.artists.clone(),
             duration: value.duration,
+            root: value.root.clone(),
+            path: value.file.clone(),
+            tags: value.tags.clone(),
+            missing: false,
+        }
+    }
+}
+
+/// A queue entry as clients see it, the song with its entry ID.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QueueSong {
+    pub entry_id: u64,
+    #[serde(flatten)]
+    pub song: Song,
+}
+
+impl From<&QueueEntry> for QueueSong {
+    fn from(value: &QueueEntry) -> Self {
+        Self {
+            entry_id: value.id,
+            song: Song::from(&value.song),
         }
     }
 }
@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use super::StateStruct;
+use crate::types::{AlbumDetails, AlbumInfo, ArtistInfo, DirListing, GenreInfo, Song, SongMeta};
+
+/// Stable ID of the artist called `name`, derived from it like song IDs are from paths.
+fn artist_id(name: &str) -> Uuid {
+    Uuid::new_v5(
+        &Uuid::NAMESPACE_URL,
+        format!("musicman:artist:{name}").as_bytes(),
+    )
+}
+
+fn album_id(artist: &str, title: &str) -> Uuid {
+    Uuid::new_v5(
+        &Uuid::NAMESPACE_URL,
+        format!("musicman:album:{artist}\0{title}").as_bytes(),
+    )
+}
+
+impl SongMeta {
+    /// Who the song's album is filed under.
+    fn album_artist(&self) -> &str {
+        self.tags
+            .album_artist
+            .as_deref()
+            .or(self.artists.first().map(String::as_str))
+            .unwrap_or("Unknown")
+    }
+
+    /// `None` for songs without an album tag.
+    fn album_id(&self) -> Option<Uuid> {
+        let title = self.tags.album.as_deref()?;
+        Some(album_id(self.album_artist(), title))
+    }
+
+    /// The track artists and the album artist, each once.
+    fn credited(&self) -> HashSet<&str> {
+        self.artists
+            .iter()
+            .map(String::as_str)
+            .chain(self.tags.album_artist.as_deref())
+            .collect()
+    }
+}
+
+/// Groups `songs` into albums, by year and then title.
+fn album_infos<'a>(songs: impl Iterator<Item = &'a SongMeta>) -> Vec<AlbumInfo> {
+    let mut albums: HashMap<Uuid, AlbumInfo> = HashMap::new();
+    for song in songs {
+        let (Some(id), Some(title)) = (song.album_id(), &song.tags.album) else {
+            continue;
+        };
+        let album = albums.entry(id).or_insert_with(|| AlbumInfo {
+            id,
+            title: title.clone(),
+            artist: song.album_artist().to_string(),
+            year: None,
+            songs: 0,
+            duration: Duration::ZERO,
+        });
+        album.songs += 1;
+        album.duration += song.duration;
+        album.year = match (album.year, song.tags.year) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+    let mut albums: Vec<AlbumInfo> = albums.into_values().collect();
+    albums.sort_by_key(|a| (a.year, a.title.to_lowercase()));
+    albums
+}
+
+impl StateStruct {
+    /// Everyone credited on a track or as album artist, by name.
+    pub fn artists(&self) -> Vec<ArtistInfo> {
+        let mut artists: HashMap<&str, (HashSet<Uuid>, usize, Duration)> = HashMap::new();
+        for song in self.index.values() {
+            for name in song.credited() {
+                let (albums, songs, duration) = artists.entry(name).or_default();
+                albums.extend(song.album_id());
+                *songs += 1;
+                *duration += song.duration;
+            }
+        }
+
+        let mut artists: Vec<ArtistInfo> = artists
+            .into_iter()
+            .map(|(name, (albums, songs, duration))| ArtistInfo {
+                id: artist_id(name),
+                name: name.to_string(),
+                albums: albums.len(),
+                songs,
+                duration,
+            })
+            .collect();
+        artists.sort_by_key(|a| a.name.to_lowercase());
+        artists
+    }
+
+    /// Albums the artist with ID `id` is on, `None` if there is no such artist.
+    pub fn artist_albums(&self, id: Uuid) -> Option<Vec<AlbumInfo>> {
+        let mut known = false;
+        let mut album_ids = HashSet::new();
+        for song in self.index.values() {
+            if song
+                .credited()
+                .into_iter()
+                .any(|name| artist_id(name) == id)
+            {
+                known = true;
+                album_ids.extend(song.album_id());
+            }
+        }
+        // The whole album counts, not only the tracks the artist is on.
+        known.then(|| {
+            album_infos(
+                self.index
+                    .values()
+                    .filter(|song| song.album_id().is_some_and(|a| album_ids.contains(&a))),
+            )
+        })
+    }
+
+    pub fn album(&self, id: Uuid) -> Option<AlbumDetails> {
+        let mut songs: Vec<&SongMeta> = self
+            .index
+            .values()
+            .filter(|song| song.album_id() == Some(id))
+            .collect();
+        songs.sort_by(|a, b| {
+            (a.tags.disc, a.tags.track, &a.file).cmp(&(b.tags.disc, b.tags.track, &b.file))
+        });
+        let info = album_infos(songs.iter().copied()).pop()?;
+        Some(AlbumDetails {
+            info,
+            tracks: songs.into_iter().map(Song::from).collect(),
+        })
+    }
+
+    pub fn genres(&self) -> Vec<GenreInfo> {
+        let mut genres: BTreeMap<&str, (HashSet<Uuid>, usize, Duration)> = BTreeMap::new();
+        for song in self.index.values() {
+            for genre in &song.tags.genres {
+                let (albums, songs, duration) = genres.entry(genre).or_default();
+                albums.extend(song.album_id());
+                *songs += 1;
+                *duration += song.duration;
+            }
+        }
+        genres
+            .into_iter()
+            .map(|(name, (albums, songs, duration))| GenreInfo {
+                name: name.to_string(),
+                albums: albums.len(),
+                songs,
+                duration,
+            })
+            .collect()
+    }
+
+    /// Subdirectories and songs directly in `dir` of the root called `root`. `None` if there
+    /// is no such root or nothing indexed under `dir`.
+    pub fn list_dir(&self, root: &str, dir: &Path) -> Option<DirListing> {
+        self.library.root(root)?;
+        let mut directories = BTreeSet::new();
+        let mut songs: Vec<&SongMeta> = Vec::new();
+        let mut found = false;
+        for song in self.index.values().filter(|song| song.root == root) {
+            let Ok(rest) = song.file.strip_prefix(dir) else {
+                continue;
+            };
+            let mut parts = rest.components();
+            match (parts.next(), parts.next()) {
+                (Some(first), Some(_)) => {
+                    directories.insert(dir.join(first));
+                }
+                (Some(_), None) => songs.push(song),
+                // `dir` is the song's own path rather than a directory.
+                (None, _) => continue,
+            }
+            found = true;
+        }
+        if !found && !dir.as_os_str().is_empty() {
+            return None;
+        }
+
+        songs.sort_by(|a, b| a.file.cmp(&b.file));
+        Some(DirListing {
+            root: root.to_string(),
+            path: dir.to_path_buf(),
+            directories: directories.into_iter().collect(),
+            songs: songs.into_iter().map(Song::from).collect(),
+        })
+    }
+
+    /// Every song in `dir` of the root called `root` and below it, in path order.
+    pub fn songs_under(&self, root: &str, dir: &Path) -> Vec<SongMeta> {
+        let mut songs: Vec<SongMeta> = self
+            .index
+            .values()
+            .filter(|song| song.root == root && song.file.starts_with(dir))
+            .cloned()
+            .collect();
+        songs.sort_by(|a, b| a.file.cmp(&b.file));
+        songs
+    }
+}
@@ -0,0 +1,237 @@
+use crate::types::OutputFormat;
+use rubato::{FftFixedIn, Resampler};
+use std::f32::consts::FRAC_1_SQRT_2;
+use symphonia::core::audio::Channels;
+
+/// Input frames the resampler works on at a time.
+const CHUNK_FRAMES: usize = 1024;
+
+/// Turns decoded audio into the output format: folds or spreads the channels, then
+/// resamples. Runs on the decoder thread so the audio thread only ever copies samples.
+pub struct Converter {
+    in_channels: usize,
+    /// Gain from every input channel (inner) to every output channel (outer), `None` when
+    /// the channels pass straight through.
+    mix: Option<Vec<Vec<f32>>>,
+    resampler: Option<FftFixedIn<f32>>,
+    /// Mixed planar audio waiting for a full resampler chunk.
+    pending: Vec<Vec<f32>>,
+    resampled: Vec<Vec<f32>>,
+    /// Output frames of resampler delay still to be thrown away.
+    skip: usize,
+    /// Output frames per input frame.
+    ratio: f64,
+    /// Frames taken in and handed out since the last reset, so the flush knows where the
+    /// real audio ends and the padding starts.
+    frames_in: usize,
+    frames_out: usize,
+    frame: Vec<f32>,
+}
+
+/// Stereo weights of one input channel. Centres go to both sides at -3 dB, surrounds to
+/// their side at -3 dB and the LFE is dropped, as in the usual ITU downmix.
+fn stereo_weights(channel: Channels) -> [f32; 2] {
+    let left = Channels::FRONT_LEFT;
+    let right = Channels::FRONT_RIGHT;
+    let lfe = Channels::LFE1 | Channels::LFE2;
+    let side_left = Channels::REAR_LEFT
+        | Channels::FRONT_LEFT_CENTRE
+        | Channels::SIDE_LEFT
+        | Channels::TOP_FRONT_LEFT
+        | Channels::TOP_REAR_LEFT
+        | Channels::REAR_LEFT_CENTRE
+        | Channels::FRONT_LEFT_WIDE
+        | Channels::FRONT_LEFT_HIGH;
+    let side_right = Channels::REAR_RIGHT
+        | Channels::FRONT_RIGHT_CENTRE
+        | Channels::SIDE_RIGHT
+        | Channels::TOP_FRONT_RIGHT
+        | Channels::TOP_REAR_RIGHT
+        | Channels::REAR_RIGHT_CENTRE
+        | Channels::FRONT_RIGHT_WIDE
+        | Channels::FRONT_RIGHT_HIGH;
+
+    if left.contains(channel) {
+        [1.0, 0.0]
+    } else if right.contains(channel) {
+        [0.0, 1.0]
+    } else if lfe.contains(channel) {
+        [0.0, 0.0]
+    } else if side_left.contains(channel) {
+        [FRAC_1_SQRT_2, 0.0]
+    } else if side_right.contains(channel) {
+        [0.0, FRAC_1_SQRT_2]
+    } else {
+        [FRAC_1_SQRT_2, FRAC_1_SQRT_2]
+    }
+}
+
+/// Builds the channel matrix, or `None` if the layout already matches.
+fn mix_matrix(channels: Channels, out_channels: usize) -> Option<Vec<Vec<f32>>> {
+    let in_channels = channels.count();
+    if in_channels == out_channels {
+        return None;
+    }
+
+    // Everything goes through a stereo fold first, scaled so a full scale input on every
+    // channel can't clip.
+    let mut stereo: Vec<[f32; 2]> = if in_channels == 1 {
+        vec![[1.0, 1.0]]
+    } else {
+        channels.iter().map(stereo_weights).collect()
+    };
+    let loudest = (0..2)
+        .map(|side| stereo.iter().map(|w| w[side]).sum::<f32>())
+        .fold(1.0, f32::max);
+    for w in &mut stereo {
+        w[0] /= loudest;
+        w[1] /= loudest;
+    }
+
+    let matrix = (0..out_channels)
+        .map(|out| {
+            stereo
+                .iter()
+                .map(|w| match (out_channels, out) {
+                    (1, _) => (w[0] + w[1]) / 2.0,
+                    (_, 0) => w[0],
+                    (_, 1) => w[1],
+                    // Multichannel outputs only get the front pair.
+                    _ => 0.0,
+                })
+                .collect()
+        })
+        .collect();
+    Some(matrix)
+}
+
+impl Converter {
+    pub fn new(
+        in_rate: u32,
+        channels: Channels,
+        output: OutputFormat,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let in_channels = channels.count();
+        let out_channels = output.channels as usize;
+        let resampler = if in_rate == output.sample_rate {
+            None
+        } else {
+            Some(FftFixedIn::new(
+                in_rate as usize,
+                output.sample_rate as usize,
+                CHUNK_FRAMES,
+                2,
+                out_channels,
+            )?)
+        };
+        let resampled = match &resampler {
+            Some(r) => vec![vec![0.0; r.output_frames_max()]; out_channels],
+            None => Vec::new(),
+        };
+
+        let mut converter = Self {
+            in_channels,
+            mix: mix_matrix(channels, out_channels),
+            resampler,
+            pending: vec![Vec::new(); out_channels],
+            resampled,
+            skip: 0,
+            ratio: output.sample_rate as f64 / in_rate as f64,
+            frames_in: 0,
+            frames_out: 0,
+            frame: vec![0.0; out_channels],
+        };
+        converter.reset();
+        Ok(converter)
+    }
+
+    /// Forgets buffered audio, after a seek.
+    pub fn reset(&mut self) {
+        for channel in &mut self.pending {
+            channel.clear();
+        }
+        self.frames_in = 0;
+        self.frames_out = 0;
+        if let Some(resampler) = &mut self.resampler {
+            resampler.reset();
+            self.skip = resampler.output_delay();
+        }
+    }
+
+    /// Converts interleaved `input` and appends the interleaved result to `output`. With
+    /// resampling some audio stays buffered until enough for a chunk has come in.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        for frame in input.chunks_exact(self.in_channels) {
+            match &self.mix {
+                Some(mix) => {
+                    for (out, gains) in self.frame.iter_mut().zip(mix) {
+                        *out = gains.iter().zip(frame).map(|(g, s)| g * s).sum();
+                    }
+                }
+                None => self.frame.copy_from_slice(frame),
+            }
+
+            if self.resampler.is_none() {
+                output.extend_from_slice(&self.frame);
+            } else {
+                for (channel, s) in self.pending.iter_mut().zip(&self.frame) {
+                    channel.push(*s);
+                }
+                self.frames_in += 1;
+            }
+        }
+
+        while let Some(resampler) = &mut self.resampler {
+            if self.pending[0].len() < resampler.input_frames_next() {
+                break;
+            }
+            let Ok((consumed, produced)) =
+                resampler.process_into_buffer(&self.pending, &mut self.resampled, None)
+            else {
+                break;
+            };
+            for channel in &mut self.pending {
+                channel.drain(..consumed);
+            }
+            self.emit(produced, usize::MAX, output);
+        }
+    }
+
+    /// Pushes out everything still buffered, at the end of the stream.
+    pub fn flush(&mut self, output: &mut Vec<f32>) {
+        let target = (self.frames_in as f64 * self.ratio).round() as usize;
+        let mut input = Some(std::mem::take(&mut self.pending));
+        while self.frames_out < target
+            && let Some(resampler) = &mut self.resampler
+        {
+            // The first call takes what is left, the ones after only feed zeros to get the
+            // resampler's delay line out.
+            let Ok((_, produced)) = resampler.process_partial_into_buffer(
+                input.take().as_deref(),
+                &mut self.resampled,
+                None,
+            ) else {
+                break;
+            };
+            if produced == 0 {
+                break;
+            }
+            self.emit(produced, target, output);
+        }
+        self.pending = vec![Vec::new(); self.resampled.len()];
+    }
+
+    /// Interleaves the first `produced` resampled frames into `output`, after dropping the
+    /// delay and never going past `target` frames in total.
+    fn emit(&mut self, produced: usize, target: usize, output: &mut Vec<f32>) {
+        let skipped = self.skip.min(produced);
+        self.skip -= skipped;
+        let end = produced.min(skipped.saturating_add(target.saturating_sub(self.frames_out)));
+        for i in skipped..end {
+            for channel in &self.resampled {
+                output.push(channel[i]);
+            }
+        }
+        self.frames_out += end - skipped;
+    }
+}
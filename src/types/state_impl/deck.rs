@@ -0,0 +1,375 @@
+use super::source::{BufferSource, PlayHead};
+use crate::types::OutputFormat;
+use rodio::Source;
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::f32::consts::FRAC_PI_2;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Commands and retired tracks in flight at once. The control side drains both every tick.
+const RING_SLOTS: usize = 64;
+
+/// A decoded track, already in the deck's output format.
+pub struct Track {
+    id: u64,
+    head: PlayHead,
+    /// Linear ReplayGain factor.
+    gain: f32,
+    source: BufferSource,
+}
+
+enum DeckCommand {
+    /// Take over from whatever is playing right away, crossfading if it is set.
+    Play(Track),
+    /// Play this as soon as the current track runs out, or crossfade into its tail.
+    Queue(Track),
+    /// Drop the queued track, the current one plays out and ends.
+    Unqueue,
+    Stop,
+}
+
+/// What the deck is doing, published for the control side.
+struct DeckShared {
+    /// Id of the track the deck is playing.
+    current: AtomicU64,
+    /// Id of the last track that ran out with nothing queued behind it.
+    ended: AtomicU64,
+    /// Crossfade length in milliseconds, 0 for plain gapless.
+    crossfade_ms: AtomicU64,
+    /// Woken whenever `current` or `ended` change, so the control side hears about it right
+    /// away. Only takes a lock when a waiter is parked, and then only very briefly.
+    changed: Notify,
+}
+
+/// A track on its way out, mixed under the current one until `len` frames have passed.
+struct Fade {
+    track: Track,
+    frame: u64,
+    len: u64,
+}
+
+/// The one source that lives in the sink for the whole life of the daemon. It plays tracks
+/// back to back in a fixed output format, so the next track starts on the very next frame
+/// after the current one ends, or overlaps its tail when crossfading.
+pub struct Deck {
+    channels: u16,
+    sample_rate: u32,
+    commands: Consumer<DeckCommand>,
+    /// Finished tracks go back to the control side to be dropped off the audio thread.
+    retired: Producer<Track>,
+    shared: Arc<DeckShared>,
+    current: Option<Track>,
+    next: Option<Track>,
+    fade: Option<Fade>,
+    /// Channel of the next sample, tracks only change hands when this is 0.
+    channel: u16,
+    gain_in: f32,
+    gain_out: f32,
+}
+
+/// What happened on the deck since the last `DeckHandle::poll`.
+pub enum DeckEvent {
+    None,
+    /// The queued track took over from the current one.
+    Advanced,
+    /// The current track ran out and nothing was queued.
+    Ended,
+}
+
+/// Lets a task wait for the deck to switch tracks without holding on to the state.
+pub struct DeckWatch {
+    shared: Arc<DeckShared>,
+}
+
+impl DeckWatch {
+    /// Resolves once the deck moved on to another track or ran out since the last call.
+    pub async fn changed(&self) {
+        self.shared.changed.notified().await;
+    }
+}
+
+/// The control side of the `Deck`, owned by `StateStruct`.
+pub struct DeckHandle {
+    channels: u16,
+    sample_rate: u32,
+    commands: Producer<DeckCommand>,
+    retired: Consumer<Track>,
+    shared: Arc<DeckShared>,
+    last_id: u64,
+    playing_id: u64,
+    queued_id: u64,
+}
+
+impl Deck {
+    pub fn new(channels: u16, sample_rate: u32) -> (Deck, DeckHandle) {
+        let (commands_tx, commands_rx) = RingBuffer::new(RING_SLOTS);
+        let (retired_tx, retired_rx) = RingBuffer::new(RING_SLOTS);
+        let shared = Arc::new(DeckShared {
+            current: AtomicU64::new(0),
+            ended: AtomicU64::new(0),
+            crossfade_ms: AtomicU64::new(0),
+            changed: Notify::new(),
+        });
+
+        let deck = Deck {
+            channels,
+            sample_rate,
+            commands: commands_rx,
+            retired: retired_tx,
+            shared: shared.clone(),
+            current: None,
+            next: None,
+            fade: None,
+            channel: 0,
+            gain_in: 1.0,
+            gain_out: 0.0,
+        };
+        let handle = DeckHandle {
+            channels,
+            sample_rate,
+            commands: commands_tx,
+            retired: retired_rx,
+            shared,
+            last_id: 0,
+            playing_id: 0,
+            queued_id: 0,
+        };
+        (deck, handle)
+    }
+
+    fn retire(&mut self, track: Option<Track>) {
+        if let Some(track) = track {
+            // Only dropped here if the control side stopped draining.
+            let _ = self.retired.push(track);
+        }
+    }
+
+    fn crossfade_frames(&self) -> u64 {
+        self.shared.crossfade_ms.load(Ordering::Relaxed) * self.sample_rate as u64 / 1000
+    }
+
+    /// Makes `track` the current one. The old current track fades out over `fade_frames`,
+    /// or is cut off if that is 0.
+    fn take_over(&mut self, track: Track, fade_frames: u64) {
+        self.shared.current.store(track.id, Ordering::Release);
+        self.shared.changed.notify_one();
+        let old = self.current.replace(track);
+        let old_fade = self.fade.take();
+        self.retire(old_fade.map(|f| f.track));
+        match old {
+            Some(old) if fade_frames > 0 => {
+                self.fade = Some(Fade {
+                    track: old,
+                    frame: 0,
+                    len: fade_frames,
+                })
+            }
+            old => self.retire(old),
+        }
+    }
+
+    fn apply(&mut self, command: DeckCommand) {
+        match command {
+            DeckCommand::Play(track) => {
+                let fade_frames = self.crossfade_frames();
+                self.take_over(track, fade_frames);
+                let old = self.next.take();
+                self.retire(old);
+            }
+            DeckCommand::Queue(track) => {
+                let old = self.next.replace(track);
+                self.retire(old);
+            }
+            DeckCommand::Unqueue => {
+                let old = self.next.take();
+                self.retire(old);
+            }
+            DeckCommand::Stop => {
+                let old = self.current.take();
+                self.retire(old);
+                let old = self.next.take();
+                self.retire(old);
+                let old = self.fade.take();
+                self.retire(old.map(|f| f.track));
+            }
+        }
+    }
+
+    /// Runs between frames: picks up commands, starts a crossfade into the queued track when
+    /// the current one is close enough to its end, and moves running fades along.
+    fn start_frame(&mut self) {
+        while let Ok(command) = self.commands.pop() {
+            self.apply(command);
+        }
+
+        let fade_frames = self.crossfade_frames();
+        if fade_frames > 0
+            && self.fade.is_none()
+            && self.next.is_some()
+            && let Some(remaining) = self.current.as_ref().and_then(|t| t.head.remaining())
+        {
+            let remaining = (remaining.as_secs_f64() * self.sample_rate as f64) as u64;
+            if remaining <= fade_frames {
+                let next = self.next.take().unwrap();
+                self.take_over(next, remaining.max(1));
+            }
+        }
+
+        match &mut self.fade {
+            Some(fade) if fade.frame < fade.len => {
+                // Equal power, so the overlap doesn't dip in loudness.
+                let t = fade.frame as f32 / fade.len as f32;
+                self.gain_in = (t * FRAC_PI_2).sin();
+                self.gain_out = (t * FRAC_PI_2).cos();
+                fade.frame += 1;
+            }
+            Some(_) => {
+                let old = self.fade.take();
+                self.retire(old.map(|f| f.track));
+                self.gain_in = 1.0;
+                self.gain_out = 0.0;
+            }
+            None => {
+                self.gain_in = 1.0;
+                self.gain_out = 0.0;
+            }
+        }
+    }
+
+    fn current_sample(&mut self) -> f32 {
+        loop {
+            let Some(track) = &mut self.current else {
+                return 0.0;
+            };
+            if let Some(s) = track.source.next() {
+                return s * track.gain;
+            }
+
+            let ended = self.current.take();
+            let id = ended.as_ref().map_or(0, |t| t.id);
+            self.retire(ended);
+            match self.next.take() {
+                Some(next) => {
+                    self.shared.current.store(next.id, Ordering::Release);
+                    self.current = Some(next);
+                }
+                None => self.shared.ended.store(id, Ordering::Release),
+            }
+            self.shared.changed.notify_one();
+        }
+    }
+}
+
+impl Iterator for Deck {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.channel == 0 {
+            self.start_frame();
+        }
+        self.channel = (self.channel + 1) % self.channels;
+
+        let current = self.current_sample();
+        let outgoing = match &mut self.fade {
+            Some(fade) => fade.track.source.next().unwrap_or(0.0) * fade.track.gain,
+            None => 0.0,
+        };
+        Some(current * self.gain_in + outgoing * self.gain_out)
+    }
+}
+
+impl Source for Deck {
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl DeckHandle {
+    fn track(&mut self, source: BufferSource, gain: f32) -> Track {
+        self.last_id += 1;
+        Track {
+            id: self.last_id,
+            head: source.play_head(),
+            gain,
+            source,
+        }
+    }
+
+    fn send(&mut self, command: DeckCommand) {
+        if self.commands.push(command).is_err() {
+            tracing::error!("Deck command ring is full, dropping command.");
+        }
+    }
+
+    /// The format sources have to be converted to before they are handed over.
+    pub fn output(&self) -> OutputFormat {
+        OutputFormat {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+        }
+    }
+
+    /// Replaces whatever is playing with `source`, played at `gain`.
+    pub fn play(&mut self, source: BufferSource, gain: f32) {
+        let track = self.track(source, gain);
+        self.playing_id = track.id;
+        self.queued_id = 0;
+        self.send(DeckCommand::Play(track));
+    }
+
+    /// Lines `source` up to start the moment the current track ends.
+    pub fn queue(&mut self, source: BufferSource, gain: f32) {
+        let track = self.track(source, gain);
+        self.queued_id = track.id;
+        self.send(DeckCommand::Queue(track));
+    }
+
+    pub fn unqueue(&mut self) {
+        self.queued_id = 0;
+        self.send(DeckCommand::Unqueue);
+    }
+
+    pub fn stop(&mut self) {
+        self.playing_id = 0;
+        self.queued_id = 0;
+        self.send(DeckCommand::Stop);
+    }
+
+    pub fn set_crossfade(&self, crossfade: Duration) {
+        self.shared
+            .crossfade_ms
+            .store(crossfade.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn watch(&self) -> DeckWatch {
+        DeckWatch {
+            shared: self.shared.clone(),
+        }
+    }
+
+    pub fn poll(&mut self) -> DeckEvent {
+        while self.retired.pop().is_ok() {}
+
+        if self.queued_id != 0 && self.shared.current.load(Ordering::Acquire) == self.queued_id {
+            self.playing_id = self.queued_id;
+            self.queued_id = 0;
+            return DeckEvent::Advanced;
+        }
+        if self.playing_id != 0 && self.shared.ended.load(Ordering::Acquire) == self.playing_id {
+            self.playing_id = 0;
+            return DeckEvent::Ended;
+        }
+        DeckEvent::None
+    }
+}
@@ -0,0 +1,105 @@
+use super::StateStruct;
+use crate::types::{Event, RepeatMode, Song};
+use uuid::Uuid;
+
+/// What listeners were last told, so each tick only sends what changed since.
+#[derive(Default)]
+pub struct Published {
+    song: Option<(usize, Option<Uuid>)>,
+    queue: Vec<u64>,
+    /// Bumped by every change to the queue, MPD's playlist version.
+    queue_version: u64,
+    /// For every queue position, the version at which it last got a different entry.
+    queue_changed: Vec<u64>,
+    is_paused: bool,
+    position: u64,
+    volume: Option<(u8, bool)>,
+    modes: Option<(bool, RepeatMode, bool, bool)>,
+}
+
+impl StateStruct {
+    /// The queue's version as last published.
+    pub fn queue_version(&self) -> u64 {
+        self.published.queue_version
+    }
+
+    /// Whether the entry at `pos` is new there since queue version `version`. A version
+    /// from before the daemon restarted counts everything as changed.
+    pub fn queue_changed_since(&self, pos: usize, version: u64) -> bool {
+        version > self.published.queue_version
+            || self
+                .published
+                .queue_changed
+                .get(pos)
+                .is_none_or(|&changed| changed > version)
+    }
+
+    /// Compares the player against what was published last and emits an event for every
+    /// part that changed. Called from the watcher every tick.
+    pub fn publish_changes(&mut self) {
+        let song = (self.current_idx, self.current_song.as_ref().map(|s| s.id));
+        if self.published.song != Some(song) {
+            self.published.song = Some(song);
+            self.events.emit(Event::SongChanged {
+                current_song: self.current_song.as_ref().map(|s| Box::new(Song::from(s))),
+                current_idx: self.current_idx,
+            });
+        }
+
+        if self.published.queue.len() != self.queue.len()
+            || self
+                .published
+                .queue
+                .iter()
+                .zip(&self.queue)
+                .any(|(id, entry)| *id != entry.id)
+        {
+            let published = &mut self.published;
+            published.queue_version += 1;
+            published.queue_changed.resize(self.queue.len(), 0);
+            for (pos, entry) in self.queue.iter().enumerate() {
+                if published.queue.get(pos) != Some(&entry.id) {
+                    published.queue_changed[pos] = published.queue_version;
+                }
+            }
+            published.queue = self.queue.iter().map(|entry| entry.id).collect();
+            self.events.emit(Event::QueueChanged {
+                length: self.queue.len(),
+            });
+        }
+
+        let is_paused = self.is_paused();
+        if self.published.is_paused != is_paused {
+            self.published.is_paused = is_paused;
+            self.events.emit(Event::PauseChanged { is_paused });
+        }
+
+        if let Some(audio) = &self.audio {
+            let position = audio.get_position();
+            if self.published.position != position.as_secs() {
+                self.published.position = position.as_secs();
+                self.events.emit(Event::Position { position });
+            }
+        }
+
+        let volume = (self.settings.volume, self.settings.muted);
+        if self.published.volume != Some(volume) {
+            self.published.volume = Some(volume);
+            self.events.emit(Event::VolumeChanged {
+                volume: volume.0,
+                is_muted: volume.1,
+            });
+        }
+
+        let modes = (
+            self.shuffle.is_some(),
+            self.settings.repeat,
+            self.settings.single,
+            self.settings.consume,
+        );
+        if self.published.modes != Some(modes) {
+            self.published.modes = Some(modes);
+            self.events.emit(Event::ModesChanged);
+        }
+    }
+}
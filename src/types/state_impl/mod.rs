@@ -1,35 +1,90 @@
-use crate::types::{GetReturn, Song, Status};
-use crate::types::{SearchType, SongIndex, SongMeta};
+use crate::helpers;
+use crate::types::{
+    Events, GetReturn, QueueEntry, QueueSong, RepeatMode, ReplayGainMode, ScanProgress, Settings,
+    Song, Status,
+};
+use crate::types::{SearchType, SongAliases, SongIndex, SongMeta};
 use rodio::Sink;
 use std::sync::Arc;
 use std::time::Duration;
+use uuid::Uuid;
 
 pub struct StateStruct {
     pub current_song: Option<SongMeta>,
-    pub queue: Vec<SongMeta>,
+    pub queue: Vec<QueueEntry>,
+    /// Entry ID handed to the next song queued.
+    pub next_entry_id: u64,
     pub current_idx: usize,
     pub index: SongIndex,
+    pub aliases: SongAliases,
+    pub settings: Settings,
     pub sink: Arc<Sink>,
+    pub deck: DeckHandle,
     pub audio: Option<source::SeekableAudio>,
+    pub next_audio: Option<Preloaded>,
+    /// Play order while shuffle is on.
+    pub shuffle: Option<Shuffle>,
+    /// Playback stopped at the end of the queue or after a song in single mode, and waits
+    /// for a skip or play instead of starting again by itself.
+    pub stopped: bool,
+    /// Fingerprint of the last saved session.
+    pub session_key: u64,
+    pub events: Arc<Events>,
+    pub published: Published,
+    pub library: Arc<helpers::Library>,
+    /// The running or last library rescan.
+    pub scan: Option<ScanProgress>,
+    /// `helpers::fill_missing_loudness` is working through the index.
+    pub measuring: bool,
 }
 
+/// The queue entry already lined up on the deck behind the current song.
+pub struct Preloaded {
+    pub idx: usize,
+    pub entry: QueueEntry,
+    /// `None` if the file could not be loaded, so it is not retried every tick.
+    pub audio: Option<source::SeekableAudio>,
+}
+
+mod browse;
+mod convert;
+mod deck;
+mod events;
 mod playback;
+mod queue;
 mod search;
+mod session;
+mod shuffle;
 mod source;
 
+pub use deck::{Deck, DeckEvent, DeckHandle};
+pub use events::Published;
+pub use shuffle::Shuffle;
+
+/// Settings are saved by the setters that change them, a failure is only logged.
+async fn save_settings(settings: &Settings) {
+    if let Err(e) = helpers::save_settings(settings).await {
+        tracing::error!("Could not save settings: {e}");
+    }
+}
+
 impl StateStruct {
+    /// The ID song `id` goes by now, following aliases left by renames and older indexes.
+    pub fn resolve(&self, mut id: Uuid) -> Uuid {
+        // Bounded in case aliases ever went round in a circle.
+        for _ in 0..8 {
+            match self.aliases.get(&id) {
+                Some(&next) if !self.index.contains_key(&id) => id = next,
+                _ => break,
+            }
+        }
+        id
+    }
+
     pub fn to_status(&self) -> Status {
         Status {
-            current_song: if let Some(songmeta) = &self.current_song {
-                Some(Song::from(songmeta))
-            } else {
-                None
-            },
-            queue: self
-                .queue
-                .iter()
-                .map(|songmeta| Song::from(songmeta))
-                .collect(),
+            current_song: self.current_song.as_ref().map(Song::from),
+            queue: self.queue.iter().map(QueueSong::from).collect(),
             current_idx: self.current_idx,
             is_paused: self.is_paused(),
             position: if let Some(audio) = &self.audio {
@@ -37,42 +92,262 @@ impl StateStruct {
             } else {
                 Duration::ZERO
             },
+            crossfade: self.settings.crossfade,
+            replaygain: self.settings.replaygain,
+            volume: self.settings.volume,
+            is_muted: self.settings.muted,
+            shuffle: self.shuffle.is_some(),
+            repeat: self.settings.repeat,
+            single: self.settings.single,
+            consume: self.settings.consume,
+            is_stopped: self.stopped,
+            scan: self.scan.clone().filter(|scan| scan.running),
         }
     }
+    /// Starts the current song from the top and unpauses.
     pub async fn add(&mut self) {
+        if self.current_song.is_some() {
+            self.load_current().await;
+            self.sink.play();
+        }
+    }
+
+    /// Puts the current song on the deck, leaving pause as it is.
+    pub async fn load_current(&mut self) {
         if let Some(song) = &self.current_song {
             let song_uuid = song.id;
-            let song = self.index.get(&song_uuid).unwrap();
+            // A rescan may have dropped the song since it was queued.
+            let song = self.index.get(&song_uuid).unwrap_or(song);
             tracing::info!("Adding song_id : {song_uuid}");
 
-            self.sink.clear();
-            if let Ok(audio) = source::SeekableAudio::new(&song.path, self.sink.clone()) {
+            self.next_audio = None;
+            if let Ok(mut audio) = source::SeekableAudio::new(&song.path, self.deck.output()) {
+                let gain = self.gain_for(song);
+                if let Some(source) = audio.take_source() {
+                    self.deck.play(source, gain);
+                }
                 self.audio = Some(audio)
             } else {
                 tracing::error!("Could not load new SeekableAudio.");
+                self.deck.stop();
+                self.audio = None;
             };
+        }
+    }
 
-            self.sink.play();
+    /// Makes sure the song after the current one is decoding and queued on the deck, so it
+    /// starts without a gap.
+    pub async fn preload(&mut self) {
+        if self.audio.is_none() {
+            return;
+        }
+        let Some(idx) = self.peek_follow() else {
+            if self.next_audio.take().is_some() {
+                self.deck.unqueue();
+            }
+            return;
+        };
+        let entry = &self.queue[idx];
+        if let Some(preloaded) = &self.next_audio
+            && preloaded.idx == idx
+            && preloaded.entry.id == entry.id
+        {
+            return;
+        }
+
+        let song = &entry.song;
+        tracing::info!("Preloading song_id : {}", song.id);
+        let audio = match source::SeekableAudio::new(&song.path, self.deck.output()) {
+            Ok(mut audio) => {
+                let gain = self.gain_for(song);
+                if let Some(source) = audio.take_source() {
+                    self.deck.queue(source, gain);
+                }
+                Some(audio)
+            }
+            Err(e) => {
+                tracing::error!("Could not preload {:?}: {e}", song.path);
+                None
+            }
+        };
+        self.next_audio = Some(Preloaded {
+            idx,
+            entry: entry.clone(),
+            audio,
+        });
+    }
+
+    /// ReplayGain factor for `song` under the current mode. Looked up in the index since
+    /// loudness measured after the song was queued only lands there.
+    fn gain_for(&self, song: &SongMeta) -> f32 {
+        let replaygain = self
+            .index
+            .get(&song.id)
+            .map_or(song.replaygain, |s| s.replaygain);
+        let mode = match self.settings.replaygain {
+            // Shuffled neighbours rarely share an album.
+            ReplayGainMode::Auto if self.shuffle.is_some() => ReplayGainMode::Track,
+            mode => mode,
+        };
+        replaygain.factor(mode)
+    }
+
+    /// Called once the deck has moved on to the preloaded song.
+    pub async fn advance(&mut self) {
+        let Some(preloaded) = self.next_audio.take() else {
+            return;
+        };
+        let expected = self.peek_follow();
+        match (self.queue.get(preloaded.idx), preloaded.audio) {
+            (Some(entry), Some(audio))
+                if entry.id == preloaded.entry.id && expected == Some(preloaded.idx) =>
+            {
+                self.follow().await;
+                self.audio = Some(audio);
+            }
+            _ => {
+                // The queue changed under the preloaded song.
+                self.next(1).await;
+                self.add().await;
+            }
         }
     }
 
+    /// Toggles pause, or starts playing again when stopped.
     pub async fn pause(&mut self) {
-        if self.sink.is_paused() {
+        if self.stopped {
+            if self.current_song.is_none() {
+                self.next(1).await;
+            }
+            self.stopped = false;
+            self.add().await;
+        } else if self.sink.is_paused() {
             self.sink.play();
         } else {
             self.sink.pause();
         }
     }
+    /// Stops playback but keeps the queue and the current song.
+    pub async fn stop(&mut self) {
+        self.deck.stop();
+        self.audio = None;
+        self.next_audio = None;
+        self.stopped = true;
+    }
+
     pub async fn clear(&mut self) {
-        self.sink.clear();
+        self.stopped = false;
+        self.deck.stop();
         self.queue.clear();
         self.current_song = None;
         self.current_idx = 0;
         self.sink.play();
         self.audio = None;
+        self.next_audio = None;
+        // Whatever gets queued next starts a fresh cycle, not one built for the old queue.
+        if self.shuffle.is_some() {
+            self.shuffle = Some(Shuffle::new(0, None));
+        }
+    }
+
+    /// Turns shuffle on or off. The current song stays and the order starts over from it.
+    pub async fn set_shuffle(&mut self, on: bool) {
+        let current = self.current_song.as_ref().map(|_| self.current_idx);
+        self.shuffle = on.then(|| Shuffle::new(self.queue.len(), current));
+        self.settings.shuffle = on;
+        save_settings(&self.settings).await;
+    }
+
+    pub async fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.settings.repeat = repeat;
+        save_settings(&self.settings).await;
+    }
+
+    pub async fn set_single(&mut self, single: bool) {
+        self.settings.single = single;
+        save_settings(&self.settings).await;
+    }
+
+    pub async fn set_consume(&mut self, consume: bool) {
+        self.settings.consume = consume;
+        save_settings(&self.settings).await;
+    }
+
+    pub async fn set_crossfade(&mut self, crossfade: Duration) {
+        self.settings.crossfade = crossfade;
+        self.deck.set_crossfade(crossfade);
+        save_settings(&self.settings).await;
+    }
+
+    /// Takes effect from the next song loaded.
+    pub async fn set_replaygain(&mut self, mode: ReplayGainMode) {
+        self.settings.replaygain = mode;
+        save_settings(&self.settings).await;
+    }
+
+    /// Sets the volume in percent, clamped to 0..=100. Unmutes.
+    pub async fn set_volume(&mut self, volume: i32) {
+        self.settings.volume = volume.clamp(0, 100) as u8;
+        self.settings.muted = false;
+        self.apply_volume();
+        save_settings(&self.settings).await;
+    }
+
+    pub async fn set_muted(&mut self, muted: bool) {
+        self.settings.muted = muted;
+        self.apply_volume();
+        save_settings(&self.settings).await;
+    }
+
+    pub fn apply_volume(&self) {
+        let volume = if self.settings.muted {
+            0.0
+        } else {
+            self.settings.volume as f32 / 100.0
+        };
+        self.sink.set_volume(volume);
     }
 
     pub fn is_paused(&self) -> bool {
         self.sink.is_paused()
     }
 }
+
+#[cfg(test)]
+impl StateStruct {
+    /// A stopped player with nothing queued and `songs` indexed, library rooted at `/music`.
+    /// The sink and deck are not connected to any output.
+    pub fn for_tests(songs: impl IntoIterator<Item = SongMeta>) -> Self {
+        let settings = Settings {
+            library: vec![crate::types::LibraryRoot {
+                path: "/music".into(),
+                name: None,
+                exclude: Vec::new(),
+            }],
+            ..Default::default()
+        };
+        let (sink, _) = Sink::new();
+        let (_, deck) = Deck::new(2, 44100);
+        StateStruct {
+            current_song: None,
+            queue: Vec::new(),
+            next_entry_id: 0,
+            current_idx: 0,
+            index: songs.into_iter().map(|song| (song.id, song)).collect(),
+            aliases: SongAliases::new(),
+            library: Arc::new(helpers::Library::new(&settings.library)),
+            settings,
+            sink: Arc::new(sink),
+            deck,
+            audio: None,
+            next_audio: None,
+            shuffle: None,
+            stopped: false,
+            session_key: 0,
+            events: Arc::new(Events::default()),
+            published: Published::default(),
+            scan: None,
+            measuring: false,
+        }
+    }
+}
@@ -1,35 +1,156 @@
 use super::{GetReturn, StateStruct};
+use crate::types::RepeatMode;
 
 impl StateStruct {
+    /// Index of the current song, `None` if nothing has started yet.
+    pub(super) fn playing_idx(&self) -> Option<usize> {
+        self.current_song.as_ref().map(|_| self.current_idx)
+    }
+
+    /// Makes entry `idx` current after moving away from `previous`, which is taken out of the
+    /// queue in consume mode.
+    pub(super) async fn land_on(&mut self, idx: usize, previous: Option<usize>) -> GetReturn {
+        self.current_idx = idx;
+        if self.settings.consume
+            && let Some(previous) = previous
+        {
+            self.remove_entry(previous);
+            if self.queue.is_empty() {
+                self.stop().await;
+                self.current_song = None;
+                self.current_idx = 0;
+                return GetReturn::End;
+            }
+            // Only moves if the queue wrapped around onto the consumed song itself.
+            self.current_idx = self.current_idx.min(self.queue.len() - 1);
+        }
+
+        self.current_song
+            .replace(self.queue[self.current_idx].song.clone());
+        self.stopped = false;
+        GetReturn::Ok
+    }
+
     pub async fn prev(&mut self, n: usize) -> GetReturn {
         if self.queue.is_empty() {
             return GetReturn::QueueEmpty;
         }
 
-        let prev_idx = match self.current_song {
-            None => 0,
-            Some(_) => {
-                (self.current_idx + self.queue.len() - (n % self.queue.len())) % self.queue.len()
+        let current = self.playing_idx();
+        let len = self.queue.len();
+        let prev_idx = match (&mut self.shuffle, current) {
+            (Some(shuffle), _) => {
+                shuffle.sync(len, current);
+                match current {
+                    None => shuffle.current(),
+                    Some(_) => shuffle.back(n),
+                }
+                .unwrap_or(0)
             }
+            (None, None) => self.current_idx.min(len - 1),
+            (None, Some(idx)) if self.settings.repeat == RepeatMode::Off => idx.saturating_sub(n),
+            (None, Some(idx)) => (idx + len - (n % len)) % len,
         };
 
-        self.current_idx = prev_idx;
-        self.current_song.replace(self.queue[prev_idx].clone());
-        GetReturn::Ok
+        self.land_on(prev_idx, current).await
     }
 
+    /// Skips `n` songs. With repeat off, running past the end of the queue stops playback
+    /// and the next start is from the top.
     pub async fn next(&mut self, n: usize) -> GetReturn {
         if self.queue.is_empty() {
             return GetReturn::QueueEmpty;
         }
 
-        let next_idx = match self.current_song {
-            None => 0,
-            Some(_) => (self.current_idx + n) % self.queue.len(),
+        let current = self.playing_idx();
+        let len = self.queue.len();
+        let wraps = self.settings.repeat != RepeatMode::Off;
+        let next_idx = match (&mut self.shuffle, current) {
+            (Some(shuffle), None) => {
+                shuffle.sync(len, current);
+                shuffle.current()
+            }
+            (Some(shuffle), Some(_)) => {
+                shuffle.sync(len, current);
+                if wraps || n <= shuffle.left_in_cycle() {
+                    shuffle.forward(n)
+                } else {
+                    None
+                }
+            }
+            (None, None) => Some(self.current_idx.min(len - 1)),
+            (None, Some(idx)) if wraps || idx + n < len => Some((idx + n) % len),
+            (None, Some(_)) => None,
         };
 
-        self.current_idx = next_idx;
-        self.current_song.replace(self.queue[next_idx].clone());
-        GetReturn::Ok
+        let Some(next_idx) = next_idx else {
+            if self.settings.consume
+                && let Some(current) = current
+            {
+                self.remove_entry(current);
+            }
+            let len = self.queue.len();
+            self.stop().await;
+            self.current_song = None;
+            self.current_idx = 0;
+            if self.shuffle.is_some() {
+                self.shuffle = Some(super::Shuffle::new(len, None));
+            }
+            return GetReturn::End;
+        };
+
+        self.land_on(next_idx, current).await
+    }
+
+    /// Moves on after the current song played out by itself, where repeat one and single
+    /// apply on top of `next`.
+    pub async fn follow(&mut self) -> GetReturn {
+        if self.queue.is_empty() {
+            return GetReturn::QueueEmpty;
+        }
+        match (self.settings.repeat, self.playing_idx()) {
+            (RepeatMode::One, Some(idx)) if idx < self.queue.len() => {
+                self.current_song.replace(self.queue[idx].song.clone());
+                GetReturn::Ok
+            }
+            (_, Some(idx)) if self.settings.single => {
+                self.stop().await;
+                if self.settings.consume {
+                    // Starting again picks up with the song after it.
+                    self.remove_entry(idx);
+                    self.current_song = None;
+                }
+                GetReturn::End
+            }
+            _ => self.next(1).await,
+        }
+    }
+
+    /// Index of the song `follow` would move to, without moving. `None` if playback stops
+    /// there.
+    pub fn peek_follow(&mut self) -> Option<usize> {
+        if self.queue.is_empty() {
+            return None;
+        }
+        let current = self.playing_idx();
+        let len = self.queue.len();
+        let wraps = self.settings.repeat != RepeatMode::Off;
+        match (self.settings.repeat, current) {
+            (RepeatMode::One, Some(idx)) => return Some(idx).filter(|&idx| idx < len),
+            (_, Some(_)) if self.settings.single => return None,
+            _ => {}
+        }
+        match &mut self.shuffle {
+            Some(shuffle) => {
+                shuffle.sync(len, current);
+                if wraps || shuffle.left_in_cycle() > 0 {
+                    shuffle.peek(1)
+                } else {
+                    None
+                }
+            }
+            None if wraps || self.current_idx + 1 < len => Some((self.current_idx + 1) % len),
+            None => None,
+        }
     }
 }
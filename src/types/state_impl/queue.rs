@@ -0,0 +1,194 @@
+use super::StateStruct;
+use crate::types::{GetReturn, QueueEntry, RepeatMode, SongMeta};
+
+impl StateStruct {
+    /// Wraps `song` in a queue entry with a fresh ID.
+    fn new_entry(&mut self, song: SongMeta) -> QueueEntry {
+        self.next_entry_id += 1;
+        QueueEntry {
+            id: self.next_entry_id,
+            song,
+        }
+    }
+
+    /// Position of the entry with ID `id`.
+    pub fn entry_pos(&self, id: u64) -> Option<usize> {
+        self.queue.iter().position(|entry| entry.id == id)
+    }
+
+    /// Appends `song` to the queue and returns its entry ID.
+    pub fn push_entry(&mut self, song: SongMeta) -> u64 {
+        let entry = self.new_entry(song);
+        let id = entry.id;
+        self.queue.push(entry);
+        id
+    }
+
+    /// Takes entry `idx` out of the queue. `current_idx`, the shuffle order and the preloaded
+    /// song keep pointing at the same entries, except that removing the current entry leaves
+    /// `current_idx` on the one after it.
+    pub fn remove_entry(&mut self, idx: usize) -> Option<QueueEntry> {
+        if idx >= self.queue.len() {
+            return None;
+        }
+        let song = self.queue.remove(idx);
+
+        if idx < self.current_idx {
+            self.current_idx -= 1;
+        }
+        if let Some(shuffle) = &mut self.shuffle {
+            shuffle.remove(idx);
+        }
+        if let Some(preloaded) = &mut self.next_audio {
+            if preloaded.idx == idx {
+                self.next_audio = None;
+                self.deck.unqueue();
+            } else if preloaded.idx > idx {
+                preloaded.idx -= 1;
+            }
+        }
+        Some(song)
+    }
+
+    /// Removes entries `start..end`. If the playing song goes with them, playback carries on
+    /// with the song that followed it, or waits on it if paused.
+    pub async fn remove_range(&mut self, start: usize, end: usize) -> bool {
+        if start >= end || end > self.queue.len() {
+            return false;
+        }
+        let playing = self.playing_idx();
+        for idx in (start..end).rev() {
+            self.remove_entry(idx);
+        }
+
+        let Some(playing) = playing else {
+            return true;
+        };
+        if !(start..end).contains(&playing) {
+            return true;
+        }
+        if self.queue.is_empty() {
+            self.clear().await;
+            return true;
+        }
+
+        self.current_song = None;
+        let next = match &mut self.shuffle {
+            Some(shuffle) => shuffle.after_removed(),
+            None if self.current_idx < self.queue.len() => Some(self.current_idx),
+            None if self.settings.repeat != RepeatMode::Off => Some(0),
+            None => None,
+        };
+        match next {
+            Some(idx) if !self.stopped => {
+                self.land_on(idx, None).await;
+                // Not `add`, a paused player stays paused.
+                self.load_current().await;
+            }
+            Some(idx) => self.current_idx = idx,
+            None => {
+                self.stop().await;
+                self.current_idx = 0;
+            }
+        }
+        true
+    }
+
+    /// Brings queue entries up to date with the index after a rescan: songs that got a new
+    /// ID or new tags are refreshed, and those whose file was deleted are taken out.
+    pub async fn sync_queue(&mut self) {
+        for idx in (0..self.queue.len()).rev() {
+            let id = self.resolve(self.queue[idx].song.id);
+            match self.index.get(&id) {
+                Some(song) => self.queue[idx].song = song.clone(),
+                None => {
+                    tracing::info!(
+                        "Removing missing song {:?} from the queue.",
+                        self.queue[idx].song.path
+                    );
+                    self.remove_range(idx, idx + 1).await;
+                }
+            }
+        }
+        if let Some(song) = &self.current_song
+            && let Some(entry) = self.queue.get(self.current_idx)
+            && self.resolve(song.id) == entry.song.id
+        {
+            self.current_song = Some(entry.song.clone());
+        }
+    }
+
+    /// Moves entries `start..end` so the first of them ends up at position `to` of the
+    /// reordered queue.
+    pub fn move_range(&mut self, start: usize, end: usize, to: usize) -> bool {
+        let len = self.queue.len();
+        if start >= end || end > len || to + (end - start) > len {
+            return false;
+        }
+
+        // New position to old index, then the other way round.
+        let mut order: Vec<usize> = (0..len).collect();
+        let moved: Vec<usize> = order.drain(start..end).collect();
+        order.splice(to..to, moved);
+        let mut map = vec![0; len];
+        for (new, &old) in order.iter().enumerate() {
+            map[old] = new;
+        }
+
+        let mut old_queue: Vec<Option<QueueEntry>> = std::mem::take(&mut self.queue)
+            .into_iter()
+            .map(Some)
+            .collect();
+        self.queue = order
+            .iter()
+            .filter_map(|&old| old_queue[old].take())
+            .collect();
+
+        if self.current_idx < len {
+            self.current_idx = map[self.current_idx];
+        }
+        if let Some(preloaded) = &mut self.next_audio {
+            preloaded.idx = map[preloaded.idx];
+        }
+        if let Some(shuffle) = &mut self.shuffle {
+            shuffle.remap(&map);
+        }
+        true
+    }
+
+    /// Puts `song` right after the current one, or first in line if nothing is playing.
+    /// Returns its entry ID.
+    pub fn insert_next(&mut self, song: SongMeta) -> u64 {
+        let at = match self.playing_idx() {
+            Some(idx) => idx + 1,
+            None => self.current_idx.min(self.queue.len()),
+        };
+        let entry = self.new_entry(song);
+        let id = entry.id;
+        self.queue.insert(at, entry);
+
+        if let Some(preloaded) = &mut self.next_audio
+            && preloaded.idx >= at
+        {
+            preloaded.idx += 1;
+        }
+        if let Some(shuffle) = &mut self.shuffle {
+            shuffle.insert(at, true);
+        }
+        id
+    }
+
+    /// Makes entry `idx` the current song. The caller starts it with `add`.
+    pub async fn jump(&mut self, idx: usize) -> GetReturn {
+        if idx >= self.queue.len() {
+            return GetReturn::QueueEmpty;
+        }
+        let current = self.playing_idx();
+        if let Some(shuffle) = &mut self.shuffle {
+            shuffle.sync(self.queue.len(), current);
+            shuffle.play_next(idx);
+            shuffle.forward(1);
+        }
+        self.land_on(idx, current).await
+    }
+}
@@ -0,0 +1,87 @@
+use super::StateStruct;
+use crate::helpers;
+use crate::types::Session;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::Duration;
+
+/// While playing, the saved position is refreshed this often even if nothing else changed.
+const POSITION_EVERY: Duration = Duration::from_secs(15);
+
+impl StateStruct {
+    fn position(&self) -> Duration {
+        self.audio
+            .as_ref()
+            .map_or(Duration::ZERO, |audio| audio.get_position())
+    }
+
+    pub fn to_session(&self) -> Session {
+        Session {
+            queue: self.queue.clone(),
+            current_idx: self.current_idx,
+            has_current: self.current_song.is_some(),
+            position: self.position(),
+            paused: self.is_paused(),
+            stopped: self.stopped,
+            next_entry_id: self.next_entry_id,
+        }
+    }
+
+    /// Writes the session out if anything in it changed since the last call.
+    pub async fn save_session_if_changed(&mut self) {
+        let mut hasher = DefaultHasher::new();
+        for entry in &self.queue {
+            entry.id.hash(&mut hasher);
+        }
+        self.current_idx.hash(&mut hasher);
+        self.current_song.is_some().hash(&mut hasher);
+        self.is_paused().hash(&mut hasher);
+        self.stopped.hash(&mut hasher);
+        (self.position().as_secs() / POSITION_EVERY.as_secs()).hash(&mut hasher);
+        let key = hasher.finish();
+        if key == self.session_key {
+            return;
+        }
+
+        self.session_key = key;
+        if let Err(e) = helpers::save_session(&self.to_session()).await {
+            tracing::error!("Could not save session: {e}");
+        }
+    }
+
+    /// Puts back the queue and the current song from the last run, paused at the saved
+    /// position. Entries are refreshed from the index where it has them and kept as saved
+    /// otherwise, the index may be empty until the startup rescan rebuilds it, and the
+    /// rescan's `sync_queue` takes out songs that are really gone.
+    pub async fn restore(&mut self, session: Session) {
+        let mut queue = session.queue;
+        for entry in &mut queue {
+            if let Some(song) = self.index.get(&self.resolve(entry.song.id)) {
+                entry.song = song.clone();
+            }
+        }
+
+        self.queue = queue;
+        self.next_entry_id = session.next_entry_id;
+        self.current_idx = session.current_idx.min(self.queue.len().saturating_sub(1));
+        self.stopped = session.stopped;
+        if !session.has_current || self.queue.is_empty() {
+            return;
+        }
+
+        self.current_song = Some(self.queue[self.current_idx].song.clone());
+        if self.stopped {
+            return;
+        }
+        // Paused before anything is on the deck, so nothing from 0:00 gets out.
+        self.sink.pause();
+        self.load_current().await;
+        if let Some(audio) = &mut self.audio {
+            audio.seek(session.position);
+        }
+        tracing::info!(
+            "Restored {} queued songs, paused at {:?}.",
+            self.queue.len(),
+            session.position
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+/// The order the queue plays in while shuffle is on. `order` holds queue indices for the
+/// cycle being played and, behind it, the one before, so `prev` keeps going back across a
+/// reshuffle.
+pub struct Shuffle {
+    order: Vec<usize>,
+    /// Position of the current song in `order`.
+    pos: usize,
+    /// Where the newest cycle starts in `order`.
+    cycle_start: usize,
+    /// Queue length the order was built for.
+    len: usize,
+    /// Removing the current entry last stepped back to the one played before it, rather
+    /// than onto the one after it because nothing was played before.
+    stepped_back: bool,
+}
+
+impl Shuffle {
+    /// Starts a cycle with `current` first, so turning shuffle on doesn't skip it.
+    pub fn new(len: usize, current: Option<usize>) -> Self {
+        let mut order: Vec<usize> = (0..len).collect();
+        order.shuffle(&mut rand::rng());
+        if let Some(current) = current
+            && let Some(i) = order.iter().position(|&idx| idx == current)
+        {
+            order.swap(0, i);
+        }
+        Self {
+            order,
+            pos: 0,
+            cycle_start: 0,
+            len,
+            stepped_back: false,
+        }
+    }
+
+    /// Catches up with the queue. Songs added at the end go to random spots among the ones
+    /// still to come, anything else starts a new cycle.
+    pub fn sync(&mut self, len: usize, current: Option<usize>) {
+        if len == self.len {
+            return;
+        }
+        if len < self.len || self.order.is_empty() {
+            *self = Self::new(len, current);
+            return;
+        }
+        for idx in self.len..len {
+            self.insert(idx, false);
+        }
+    }
+
+    /// Makes room for a new queue entry at `idx`, shifting the ones from there on up. It
+    /// plays right after the current song if `up_next`, otherwise at a random point among the
+    /// songs still to come.
+    pub fn insert(&mut self, idx: usize, up_next: bool) {
+        for entry in &mut self.order {
+            if *entry >= idx {
+                *entry += 1;
+            }
+        }
+        self.len += 1;
+        if self.order.is_empty() {
+            self.order.push(idx);
+            return;
+        }
+        let at = if up_next {
+            self.pos + 1
+        } else {
+            rand::rng().random_range(self.pos + 1..=self.order.len())
+        };
+        self.insert_at(at, idx);
+    }
+
+    /// Moves queue entry `idx` up to play right after the current song.
+    pub fn play_next(&mut self, idx: usize) {
+        if self.order.is_empty() {
+            return;
+        }
+        let mut i = self.pos + 1;
+        while i < self.order.len() {
+            if self.order[i] == idx {
+                self.order.remove(i);
+                if i < self.cycle_start {
+                    self.cycle_start -= 1;
+                }
+            } else {
+                i += 1;
+            }
+        }
+        self.insert_at(self.pos + 1, idx);
+    }
+
+    /// Follows the queue being reordered, `map` gives every old index its new one.
+    pub fn remap(&mut self, map: &[usize]) {
+        for entry in &mut self.order {
+            *entry = map[*entry];
+        }
+    }
+
+    fn insert_at(&mut self, at: usize, idx: usize) {
+        self.order.insert(at, idx);
+        if self.pos < self.cycle_start && at <= self.cycle_start {
+            self.cycle_start += 1;
+        }
+    }
+
+    /// Forgets queue entry `idx` and shifts the ones after it down. If it was the current
+    /// one, the one played before it becomes current so `forward` still lands on what came
+    /// next, or if there is none the one after it does. `after_removed` sorts that out.
+    pub fn remove(&mut self, idx: usize) {
+        let before = self.order[..self.pos.min(self.order.len())]
+            .iter()
+            .filter(|&&entry| entry == idx)
+            .count();
+        let before_cycle = self.order[..self.cycle_start.min(self.order.len())]
+            .iter()
+            .filter(|&&entry| entry == idx)
+            .count();
+        let was_current = self.current() == Some(idx);
+
+        self.order.retain(|&entry| entry != idx);
+        for entry in &mut self.order {
+            if *entry > idx {
+                *entry -= 1;
+            }
+        }
+        self.pos -= before;
+        if was_current {
+            self.stepped_back = self.pos > 0;
+            if self.stepped_back {
+                self.pos -= 1;
+            }
+        }
+        self.pos = self.pos.min(self.order.len().saturating_sub(1));
+        self.cycle_start -= before_cycle;
+        self.len = self.len.saturating_sub(1);
+    }
+
+    /// Moves on to the song that followed the current one after `remove` took it out.
+    pub fn after_removed(&mut self) -> Option<usize> {
+        if self.stepped_back {
+            self.forward(1)
+        } else {
+            self.current()
+        }
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.order.get(self.pos).copied()
+    }
+
+    /// Songs still to come before the cycle being played runs out.
+    pub fn left_in_cycle(&self) -> usize {
+        let end = if self.pos < self.cycle_start {
+            self.cycle_start
+        } else {
+            self.order.len()
+        };
+        end.saturating_sub(self.pos + 1)
+    }
+
+    /// Queue index `n` songs ahead, without moving there.
+    pub fn peek(&mut self, n: usize) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        while self.pos + n >= self.order.len() {
+            self.reshuffle();
+        }
+        Some(self.order[self.pos + n])
+    }
+
+    pub fn forward(&mut self, n: usize) -> Option<usize> {
+        let idx = self.peek(n)?;
+        self.pos += n;
+        Some(idx)
+    }
+
+    /// Goes back through the songs played, stopping at the oldest one remembered.
+    pub fn back(&mut self, n: usize) -> Option<usize> {
+        self.pos = self.pos.saturating_sub(n);
+        self.current()
+    }
+
+    /// Appends a fresh cycle, dropping history older than the one before it.
+    fn reshuffle(&mut self) {
+        let old = self.cycle_start.min(self.pos);
+        self.order.drain(..old);
+        self.pos -= old;
+        self.cycle_start = self.order.len();
+
+        let mut cycle: Vec<usize> = (0..self.len).collect();
+        cycle.shuffle(&mut rand::rng());
+        // Don't play the same song twice in a row across the boundary.
+        if cycle.len() > 1 && cycle.first() == self.order.last() {
+            let last = cycle.len() - 1;
+            cycle.swap(0, last);
+        }
+        self.order.extend(cycle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The entry after `current` once `current` is gone from the queue.
+    fn shifted(entry: usize, removed: usize) -> usize {
+        if entry > removed { entry - 1 } else { entry }
+    }
+
+    #[test]
+    fn removing_the_first_song_played_lands_on_the_next() {
+        for _ in 0..50 {
+            let mut shuffle = Shuffle::new(5, Some(2));
+            assert_eq!(shuffle.current(), Some(2));
+            let next = shuffle.peek(1).unwrap();
+
+            shuffle.remove(2);
+            assert_eq!(shuffle.after_removed(), Some(shifted(next, 2)));
+        }
+    }
+
+    #[test]
+    fn removing_a_later_song_lands_on_the_next() {
+        for _ in 0..50 {
+            let mut shuffle = Shuffle::new(5, Some(0));
+            let current = shuffle.forward(2).unwrap();
+            let next = shuffle.peek(1).unwrap();
+
+            shuffle.remove(current);
+            assert_eq!(shuffle.after_removed(), Some(shifted(next, current)));
+        }
+    }
+
+    #[test]
+    fn removing_the_only_song_leaves_nothing() {
+        let mut shuffle = Shuffle::new(1, Some(0));
+        shuffle.remove(0);
+        assert_eq!(shuffle.after_removed(), None);
+    }
+
+    #[test]
+    fn removing_another_song_keeps_the_current_one() {
+        let mut shuffle = Shuffle::new(5, Some(3));
+        let next = shuffle.peek(1).unwrap();
+        let other = (0..5).find(|&idx| idx != 3 && idx != next).unwrap();
+
+        shuffle.remove(other);
+        assert_eq!(shuffle.current(), Some(shifted(3, other)));
+        assert_eq!(shuffle.forward(1), Some(shifted(next, other)));
+    }
+}
@@ -1,81 +1,148 @@
-use rodio::{Sink, Source};
+use super::convert::Converter;
+use crate::helpers;
+use crate::types::OutputFormat;
+use rodio::Source;
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::collections::VecDeque;
 use std::fs::File;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread::Thread;
 use std::time::Duration;
-use symphonia::core::audio::SampleBuffer;
+use symphonia::core::audio::{SampleBuffer, SignalSpec};
 use symphonia::core::codecs::{CODEC_TYPE_NULL, DecoderOptions};
 use symphonia::core::errors::Error;
-use symphonia::core::formats::FormatOptions;
+use symphonia::core::formats::{FormatOptions, SeekMode, SeekTo};
 use symphonia::core::io::MediaSourceStream;
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
-
-pub struct AudioBuffer {
-    samples: Vec<f32>,
-    sample_rate: u32,
-    channels: u16,
-    fully_loaded: bool,
-    duration: Duration,
+use symphonia::core::units::Time;
+
+/// How much decoded audio the decoder thread keeps queued ahead of the play head.
+const WINDOW_AHEAD: Duration = Duration::from_secs(20);
+/// How much already played audio the source holds on to, so short seeks backwards replay it
+/// instead of making the decoder seek.
+const WINDOW_BEHIND: Duration = Duration::from_secs(10);
+/// Slots in the chunk rings. Chunks are one decoded packet each, so this is never the limit.
+const RING_SLOTS: usize = 4096;
+
+/// Format parameters of a track as it comes out of the decoder thread, already converted to
+/// the output format. Fixed once the file is probed, so both ends copy them.
+#[derive(Clone, Copy)]
+pub struct StreamFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub duration: Duration,
 }
 
-impl AudioBuffer {
-    fn new(sample_rate: u32, channels: u16, duration: Duration) -> Self {
-        Self {
-            samples: Vec::new(),
-            sample_rate,
-            duration,
-            fully_loaded: false,
-            channels: channels,
-        }
+impl StreamFormat {
+    fn sample_index_from_time(&self, pos: Duration) -> usize {
+        let frame = (pos.as_secs_f64() * self.sample_rate as f64).round() as usize;
+        frame * self.channels as usize
     }
 
-    fn push_samples(&mut self, new: &[f32]) {
-        self.samples.extend_from_slice(new);
+    fn time_from_sample_index(&self, idx: usize) -> Duration {
+        let frame = idx / self.channels as usize;
+        Duration::from_secs_f64(frame as f64 / self.sample_rate as f64)
     }
+}
 
-    fn sample_index_from_time(&self, pos: Duration) -> usize {
-        let frame = (pos.as_secs_f64() * self.sample_rate as f64) as usize;
-        frame * self.channels as usize
-    }
+/// One decoded packet handed from the decoder thread to the audio thread.
+struct Chunk {
+    /// Seek generation the chunk was decoded for, stale chunks are dropped by the source.
+    generation: u64,
+    /// Absolute sample index of `samples[0]` within the track.
+    start: usize,
+    samples: Vec<f32>,
+}
+
+/// State shared between the decoder thread, the `BufferSource` and `SeekableAudio`.
+/// Everything is atomic so the audio thread never waits on anyone.
+struct Shared {
+    /// Absolute sample index of the next sample the source will play.
+    position: AtomicUsize,
+    /// Bumped on every seek the decoder has to follow.
+    generation: AtomicU64,
+    /// Bumped on seeks back into audio the source still holds, the decoder carries on as is.
+    rewinds: AtomicU64,
+    /// Target of the latest seek of either kind.
+    seek_target: AtomicUsize,
+    /// Absolute sample index of the oldest sample the source holds, `usize::MAX` if none.
+    held_from: AtomicUsize,
+    /// Generation + 1 of the last generation the decoder hit the end of, 0 if none.
+    finished: AtomicU64,
+    /// Samples currently sitting in the chunk ring.
+    buffered: AtomicUsize,
+    /// The decoder thread died, nothing more is coming.
+    failed: AtomicBool,
+    closed: AtomicBool,
 }
 
 pub struct SeekableAudio {
-    sink: Arc<Sink>,
-    buffer: Arc<Mutex<AudioBuffer>>,
+    format: StreamFormat,
+    shared: Arc<Shared>,
+    decoder: Thread,
     source: Option<BufferSource>,
 }
 
 impl SeekableAudio {
-    pub fn new(path: &Path, sink: Arc<Sink>) -> Result<Self, Box<dyn std::error::Error>> {
-        let (sample_rate, channels, duration) = Self::read_metadata(path)?;
-        let buffer = Arc::new(Mutex::new(AudioBuffer::new(
-            sample_rate,
-            channels,
-            duration,
-        )));
-
-        {
+    pub fn new(path: &Path, output: OutputFormat) -> Result<Self, Box<dyn std::error::Error>> {
+        let format = Self::read_metadata(path, output)?;
+        let shared = Arc::new(Shared {
+            position: AtomicUsize::new(0),
+            generation: AtomicU64::new(0),
+            rewinds: AtomicU64::new(0),
+            seek_target: AtomicUsize::new(0),
+            held_from: AtomicUsize::new(usize::MAX),
+            finished: AtomicU64::new(0),
+            buffered: AtomicUsize::new(0),
+            failed: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+        });
+        let (chunk_tx, chunk_rx) = RingBuffer::new(RING_SLOTS);
+        let (spent_tx, spent_rx) = RingBuffer::new(RING_SLOTS);
+
+        let decoder = {
             let path = path.to_owned();
-            let buffer_clone = buffer.clone();
+            let shared = shared.clone();
             std::thread::spawn(move || {
-                let _ = Self::decode_streaming(path, buffer_clone);
-            });
-        }
-        let buf = BufferSource::new(buffer.clone(), 0);
-        sink.append(buf.clone());
-        sink.pause();
+                if let Err(e) =
+                    Self::decode_streaming(path, format, shared.clone(), chunk_tx, spent_rx)
+                {
+                    tracing::error!("Decoder stopped: {e}");
+                    shared.failed.store(true, Ordering::Release);
+                }
+            })
+            .thread()
+            .clone()
+        };
+
+        let buf = BufferSource {
+            format,
+            shared: shared.clone(),
+            chunks: chunk_rx,
+            spent: spent_tx,
+            held: VecDeque::with_capacity(RING_SLOTS),
+            playing: 0,
+            cursor: 0,
+            behind: format.sample_index_from_time(WINDOW_BEHIND),
+            generation: 0,
+            rewinds: 0,
+        };
 
         Ok(Self {
-            sink,
-            buffer,
+            format,
+            shared,
+            decoder,
             source: Some(buf),
         })
     }
 
-    fn read_metadata(path: &Path) -> Result<(u32, u16, Duration), Box<dyn std::error::Error>> {
+    fn read_metadata(
+        path: &Path,
+        output: OutputFormat,
+    ) -> Result<StreamFormat, Box<dyn std::error::Error>> {
         let file = Box::new(File::open(path)?);
         let mss = MediaSourceStream::new(file, Default::default());
 
@@ -98,29 +165,30 @@ impl SeekableAudio {
             .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
             .ok_or("No track")?;
 
-        let rate = track
-            .codec_params
-            .sample_rate
-            .ok_or("Missing sample rate")?;
-        let channels = track
-            .codec_params
-            .channels
-            .ok_or("Missing channels")?
-            .count() as u16;
-
         let mut duration = Duration::ZERO;
-        if let Some(tb) = track.codec_params.time_base {
-            if let Some(frames) = track.codec_params.n_frames {
-                duration = tb.calc_time(frames).into();
-            }
+        if let Some(tb) = track.codec_params.time_base
+            && let Some(frames) = track.codec_params.n_frames
+        {
+            duration = tb.calc_time(frames).into();
         }
 
-        Ok((rate, channels, duration))
+        Ok(StreamFormat {
+            sample_rate: output.sample_rate,
+            channels: output.channels,
+            duration,
+        })
     }
 
+    /// Decodes `path` into the chunk ring, staying at most `WINDOW_AHEAD` in front of the
+    /// play head and jumping with `FormatReader::seek` whenever the seek generation changes.
+    /// Every packet goes through a `Converter` on the way, rebuilt if the stream's spec
+    /// changes halfway.
     fn decode_streaming(
         path: PathBuf,
-        buffer: Arc<Mutex<AudioBuffer>>,
+        format: StreamFormat,
+        shared: Arc<Shared>,
+        mut chunks: Producer<Chunk>,
+        mut spent: Consumer<Vec<f32>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let file = Box::new(File::open(&path)?);
         let mss = MediaSourceStream::new(file, Default::default());
@@ -145,15 +213,89 @@ impl SeekableAudio {
             .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
             .ok_or("No audio track")?;
 
-        let mut decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &DecoderOptions::default())?;
+        let mut decoder =
+            helpers::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
 
         let track_id = track.id;
+        let time_base = track.codec_params.time_base;
+        let ahead_limit = format.sample_index_from_time(WINDOW_AHEAD);
+
+        let mut generation = 0;
+        // Absolute sample index of the next decoded sample.
+        let mut next_start = 0;
+        let mut at_end = false;
+        let mut converter: Option<(SignalSpec, Converter)> = None;
+        let output = OutputFormat {
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+        };
 
         loop {
+            if shared.closed.load(Ordering::Acquire) || chunks.is_abandoned() {
+                return Ok(());
+            }
+
+            let current = shared.generation.load(Ordering::Acquire);
+            if current != generation {
+                generation = current;
+                at_end = false;
+                let target = shared.seek_target.load(Ordering::Acquire);
+                let time = format.time_from_sample_index(target);
+                let seeked = reader.seek(
+                    SeekMode::Accurate,
+                    SeekTo::Time {
+                        time: Time::from(time.as_secs_f64()),
+                        track_id: Some(track_id),
+                    },
+                );
+                decoder.reset();
+                if let Some((_, converter)) = &mut converter {
+                    converter.reset();
+                }
+                match seeked {
+                    Ok(seeked) => {
+                        // The reader may land a little before the requested time, the source
+                        // skips forward to the target itself.
+                        next_start = match time_base {
+                            Some(tb) => {
+                                let actual: Duration = tb.calc_time(seeked.actual_ts).into();
+                                format.sample_index_from_time(actual.min(time))
+                            }
+                            None => target,
+                        };
+                    }
+                    Err(e) => {
+                        tracing::warn!("Could not seek {:?}: {e}", path);
+                        at_end = true;
+                        shared.finished.store(generation + 1, Ordering::Release);
+                    }
+                }
+                continue;
+            }
+
+            if at_end && shared.finished.load(Ordering::Acquire) != generation + 1 {
+                // Push out what the resampler still holds before the source may end.
+                if let Some((_, converter)) = &mut converter {
+                    let mut samples = spent.pop().unwrap_or_default();
+                    samples.clear();
+                    converter.flush(&mut samples);
+                    Self::send(&mut chunks, &shared, generation, &mut next_start, samples);
+                }
+                shared.finished.store(generation + 1, Ordering::Release);
+            }
+
+            if at_end || chunks.is_full() || shared.buffered.load(Ordering::Acquire) >= ahead_limit
+            {
+                std::thread::park_timeout(Duration::from_millis(20));
+                continue;
+            }
+
             let packet = match reader.next_packet() {
                 Ok(p) => p,
-                Err(Error::IoError(_)) => break,
+                Err(Error::IoError(_)) => {
+                    at_end = true;
+                    continue;
+                }
                 Err(e) => return Err(e.into()),
             };
 
@@ -167,65 +309,206 @@ impl SeekableAudio {
                     let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
                     buf.copy_interleaved_ref(decoded);
 
-                    buffer.lock().unwrap().push_samples(buf.samples());
+                    let converter = match &mut converter {
+                        Some((current, converter)) if *current == spec => converter,
+                        _ => {
+                            let new = Converter::new(spec.rate, spec.channels, output)?;
+                            &mut converter.insert((spec, new)).1
+                        }
+                    };
+
+                    let mut samples = spent.pop().unwrap_or_default();
+                    samples.clear();
+                    converter.process(buf.samples(), &mut samples);
+                    Self::send(&mut chunks, &shared, generation, &mut next_start, samples);
                 }
 
                 Err(Error::DecodeError(_)) => continue,
 
-                Err(Error::IoError(_)) => break,
+                Err(Error::IoError(_)) => at_end = true,
                 Err(e) => return Err(e.into()),
             }
         }
+    }
+
+    /// Hands converted samples to the source. Nothing goes out while the resampler is still
+    /// filling up.
+    fn send(
+        chunks: &mut Producer<Chunk>,
+        shared: &Shared,
+        generation: u64,
+        next_start: &mut usize,
+        samples: Vec<f32>,
+    ) {
+        if samples.is_empty() {
+            return;
+        }
+        let len = samples.len();
+        shared.buffered.fetch_add(len, Ordering::AcqRel);
+        let chunk = Chunk {
+            generation,
+            start: *next_start,
+            samples,
+        };
+        if chunks.push(chunk).is_err() {
+            shared.buffered.fetch_sub(len, Ordering::AcqRel);
+        }
+        *next_start += len;
+    }
 
-        buffer.lock().unwrap().fully_loaded = true;
-        Ok(())
+    /// The playable end of this audio, handed to the deck once.
+    pub fn take_source(&mut self) -> Option<BufferSource> {
+        self.source.take()
     }
 
     pub fn get_position(&self) -> Duration {
-        if let Some(s) = &self.source {
-            s.current_position()
+        self.format
+            .time_from_sample_index(self.shared.position.load(Ordering::Relaxed))
+    }
+
+    /// Seeks within the audio the source still holds are handled by the source alone, anything
+    /// else starts a new generation and makes the decoder seek.
+    pub fn seek(&mut self, pos: Duration) {
+        let idx = self.format.sample_index_from_time(pos);
+        let held_from = self.shared.held_from.load(Ordering::Acquire);
+        let position = self.shared.position.load(Ordering::Relaxed);
+        self.shared.seek_target.store(idx, Ordering::Release);
+        // The source stores it again once it picks up the seek.
+        self.shared.position.store(idx, Ordering::Relaxed);
+        if held_from <= idx && idx <= position {
+            self.shared.rewinds.fetch_add(1, Ordering::AcqRel);
         } else {
-            Duration::ZERO
+            self.shared.generation.fetch_add(1, Ordering::AcqRel);
+            self.decoder.unpark();
         }
     }
+}
 
-    pub fn seek(&mut self, pos: Duration) {
-        let idx = {
-            let buf = self.buffer.lock().unwrap();
-            buf.sample_index_from_time(pos)
-        };
+impl Drop for SeekableAudio {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.decoder.unpark();
+    }
+}
 
-        let was_paused = self.sink.is_paused();
+/// The audio thread end of the handoff. `next` only touches atomics and the chunk rings,
+/// and plays silence instead of waiting when the decoder falls behind.
+pub struct BufferSource {
+    format: StreamFormat,
+    shared: Arc<Shared>,
+    chunks: Consumer<Chunk>,
+    /// Played out chunk buffers going back to the decoder for reuse, so nothing is freed here.
+    spent: Producer<Vec<f32>>,
+    /// Chunks taken off the ring, oldest first: up to `WINDOW_BEHIND` of played audio and the
+    /// one playing. Allocated up front so the audio thread never grows it.
+    held: VecDeque<Chunk>,
+    /// Index into `held` of the chunk playing, `held.len()` when the next one is due.
+    playing: usize,
+    cursor: usize,
+    /// `WINDOW_BEHIND` in samples.
+    behind: usize,
+    generation: u64,
+    rewinds: u64,
+}
 
-        self.sink.stop();
-        let source = BufferSource::new(self.buffer.clone(), idx);
-        self.source = Some(source.clone());
-        self.sink.append(source);
+/// Where a `BufferSource` is in its track, for code that only holds it wrapped up.
+pub struct PlayHead {
+    format: StreamFormat,
+    shared: Arc<Shared>,
+}
 
-        if !was_paused {
-            self.sink.play();
+impl PlayHead {
+    /// Time left until the end of the track, `None` if the file did not say how long it is.
+    pub fn remaining(&self) -> Option<Duration> {
+        if self.format.duration.is_zero() {
+            return None;
         }
+        let pos = self
+            .format
+            .time_from_sample_index(self.shared.position.load(Ordering::Relaxed));
+        Some(self.format.duration.saturating_sub(pos))
     }
 }
 
-#[derive(Clone)]
-struct BufferSource {
-    buffer: Arc<Mutex<AudioBuffer>>,
-    position: Arc<AtomicUsize>,
-}
-
 impl BufferSource {
-    fn new(buffer: Arc<Mutex<AudioBuffer>>, start_pos: usize) -> Self {
-        Self {
-            buffer,
-            position: Arc::new(AtomicUsize::from(start_pos)),
+    pub fn play_head(&self) -> PlayHead {
+        PlayHead {
+            format: self.format,
+            shared: self.shared.clone(),
+        }
+    }
+
+    fn recycle(&mut self, chunk: Chunk) {
+        // If the decoder is gone or the ring is full the buffer is simply dropped.
+        let _ = self.spent.push(chunk.samples);
+    }
+
+    /// Takes a chunk off the ring. It stops counting towards the decoder's window here, and
+    /// stale generations go straight back.
+    fn pop(&mut self) -> Option<Chunk> {
+        while let Ok(chunk) = self.chunks.pop() {
+            self.shared
+                .buffered
+                .fetch_sub(chunk.samples.len(), Ordering::AcqRel);
+            if chunk.generation == self.generation {
+                return Some(chunk);
+            }
+            self.recycle(chunk);
+        }
+        None
+    }
+
+    fn release_held(&mut self) {
+        while let Some(chunk) = self.held.pop_front() {
+            self.recycle(chunk);
         }
+        self.playing = 0;
+        self.shared.held_from.store(usize::MAX, Ordering::Release);
     }
-    pub fn current_position(&self) -> Duration {
-        let idx = self.position.load(Ordering::Relaxed);
-        let buf = self.buffer.lock().unwrap();
-        let frame = idx / buf.channels as usize;
-        Duration::from_secs_f64(frame as f64 / buf.sample_rate as f64)
+
+    /// Lets go of played chunks that are further than `WINDOW_BEHIND` behind `position`, or
+    /// the oldest one if `held` is full.
+    fn trim_held(&mut self, position: usize) {
+        while self.playing > 0
+            && let Some(oldest) = self.held.front()
+            && (oldest.start + oldest.samples.len() + self.behind <= position
+                || self.held.len() == self.held.capacity())
+        {
+            let chunk = self.held.pop_front().unwrap();
+            self.recycle(chunk);
+            self.playing -= 1;
+        }
+        let held_from = self.held.front().map_or(usize::MAX, |c| c.start);
+        self.shared.held_from.store(held_from, Ordering::Release);
+    }
+
+    /// Moves the play head back to `target` within `held`. If it is not in there any more,
+    /// falls back to a regular seek, the decoder picks the new generation up on its next wake.
+    fn rewind(&mut self, target: usize) {
+        let found = self
+            .held
+            .iter()
+            .position(|c| c.start <= target && target < c.start + c.samples.len());
+        match found {
+            Some(i) => {
+                self.playing = i;
+                self.cursor = target - self.held[i].start;
+            }
+            // Right where the ring picks up.
+            None if self
+                .held
+                .back()
+                .is_some_and(|c| c.start + c.samples.len() == target) =>
+            {
+                self.playing = self.held.len();
+                self.cursor = 0;
+            }
+            None => {
+                self.release_held();
+                self.generation = self.shared.generation.fetch_add(1, Ordering::AcqRel) + 1;
+            }
+        }
+        self.shared.position.store(target, Ordering::Relaxed);
     }
 }
 
@@ -233,36 +516,273 @@ impl Iterator for BufferSource {
     type Item = f32;
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            let buf = self.buffer.lock().unwrap();
+        let generation = self.shared.generation.load(Ordering::Acquire);
+        if generation != self.generation {
+            self.generation = generation;
+            self.release_held();
+            let target = self.shared.seek_target.load(Ordering::Acquire);
+            self.shared.position.store(target, Ordering::Relaxed);
+        }
+        // Checked after the generation so a rewind requested after a regular seek still lands,
+        // `seek_target` always holds the latest one.
+        let rewinds = self.shared.rewinds.load(Ordering::Acquire);
+        if rewinds != self.rewinds {
+            self.rewinds = rewinds;
+            let target = self.shared.seek_target.load(Ordering::Acquire);
+            self.rewind(target);
+        }
 
-            if self.position.load(Ordering::Relaxed) < buf.samples.len() {
-                let s = buf.samples[self.position.load(Ordering::Relaxed)];
-                self.position.fetch_add(1, Ordering::Relaxed);
-                return Some(s);
+        loop {
+            if let Some(chunk) = self.held.get(self.playing) {
+                if self.cursor < chunk.samples.len() {
+                    let s = chunk.samples[self.cursor];
+                    self.cursor += 1;
+                    self.shared
+                        .position
+                        .store(chunk.start + self.cursor, Ordering::Relaxed);
+                    return Some(s);
+                }
+                let end = chunk.start + chunk.samples.len();
+                self.playing += 1;
+                self.cursor = 0;
+                self.trim_held(end);
+                continue;
             }
 
-            if buf.fully_loaded {
-                return None;
-            }
+            // Read `finished` before checking the ring, everything pushed before it is visible.
+            let finished = self.shared.finished.load(Ordering::Acquire) == self.generation + 1
+                || self.shared.failed.load(Ordering::Acquire);
+            let Some(chunk) = self.pop() else {
+                if finished {
+                    return None;
+                }
+                // Underrun, keep the output running rather than block the audio thread.
+                return Some(0.0);
+            };
 
-            drop(buf);
-            std::thread::sleep(Duration::from_millis(3));
+            // After a seek the first chunk can start before the target.
+            let target = self.shared.position.load(Ordering::Relaxed);
+            self.cursor = target.saturating_sub(chunk.start);
+            let position = chunk.start + self.cursor;
+            self.held.push_back(chunk);
+            self.trim_held(position);
         }
     }
 }
 
 impl Source for BufferSource {
     fn channels(&self) -> u16 {
-        self.buffer.lock().unwrap().channels
+        self.format.channels
     }
     fn sample_rate(&self) -> u32 {
-        self.buffer.lock().unwrap().sample_rate
+        self.format.sample_rate
     }
     fn current_span_len(&self) -> Option<usize> {
         None
     }
     fn total_duration(&self) -> Option<Duration> {
-        Some(self.buffer.lock().unwrap().duration)
+        Some(self.format.duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::time::Instant;
+
+    const RATE: u32 = 44100;
+
+    /// Writes a 16 bit stereo WAV where every frame holds its own index (mod 30000, plus one so
+    /// no frame is silent), so a sample tells where in the file it came from.
+    fn ramp_wav(name: &str, seconds: u32) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("musicman-{}-{name}.wav", std::process::id()));
+        let frames = RATE * seconds;
+        let data_len = frames * 4;
+        let mut bytes = Vec::with_capacity(44 + data_len as usize);
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&RATE.to_le_bytes());
+        bytes.extend_from_slice(&(RATE * 4).to_le_bytes());
+        bytes.extend_from_slice(&4u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for frame in 0..frames {
+            let value = ramp(frame as usize);
+            bytes.extend_from_slice(&value.to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        File::create(&path).unwrap().write_all(&bytes).unwrap();
+        path
+    }
+
+    fn ramp(frame: usize) -> i16 {
+        (frame % 30000) as i16 + 1
+    }
+
+    fn frame_of(sample: f32) -> usize {
+        (sample * 32768.0).round() as usize - 1
+    }
+
+    fn open(path: &Path, sample_rate: u32) -> (SeekableAudio, BufferSource) {
+        let output = OutputFormat {
+            sample_rate,
+            channels: 2,
+        };
+        let mut audio = SeekableAudio::new(path, output).unwrap();
+        let source = audio.take_source().unwrap();
+        (audio, source)
+    }
+
+    /// Plays until the play head reaches `frame`.
+    fn play_to(source: &mut BufferSource, frame: usize) {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while source.shared.position.load(Ordering::Relaxed) < frame * 2 {
+            assert!(Instant::now() < deadline, "never got to frame {frame}");
+            source.next().unwrap();
+        }
+    }
+
+    /// The next sample that is not underrun silence.
+    fn next_sound(source: &mut BufferSource) -> f32 {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        loop {
+            assert!(Instant::now() < deadline, "nothing but silence");
+            let s = source.next().unwrap();
+            if s != 0.0 {
+                return s;
+            }
+        }
+    }
+
+    /// Calls `next` up to `limit` times or until the source ends. Returns how many samples were
+    /// not underrun silence.
+    fn drain(source: &mut BufferSource, limit: usize) -> usize {
+        let mut played = 0;
+        for _ in 0..limit {
+            let Some(s) = source.next() else { break };
+            if s != 0.0 {
+                played += 1;
+            }
+        }
+        played
+    }
+
+    /// Samples an output callback asks for at a time, 512 stereo frames.
+    const BLOCK: usize = 1024;
+
+    /// Times `next` one output block at a time until the source ends or `blocks` are done,
+    /// and prints how long the blocks took.
+    fn bench(name: &str, source: &mut BufferSource, blocks: usize) {
+        let mut times = Vec::new();
+        'blocks: for _ in 0..blocks {
+            let started = Instant::now();
+            for _ in 0..BLOCK {
+                if std::hint::black_box(source.next()).is_none() {
+                    break 'blocks;
+                }
+            }
+            times.push(started.elapsed());
+        }
+        times.sort();
+        let at = |quantile: f64| times[((times.len() - 1) as f64 * quantile) as usize];
+        println!(
+            "{name}: {} blocks of {BLOCK} samples, p50 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+            times.len(),
+            at(0.5),
+            at(0.99),
+            at(0.999),
+            at(1.0),
+        );
+    }
+
+    /// Pretends the window ahead is full, so the decoder parks and sends nothing more.
+    fn stall(source: &BufferSource) -> usize {
+        let stall = usize::MAX / 2;
+        source.shared.buffered.fetch_add(stall, Ordering::AcqRel);
+        stall
+    }
+
+    #[test]
+    fn next_does_not_wait_for_a_stalled_decoder() {
+        let path = ramp_wav("stalled", 2);
+        let (_audio, mut source) = open(&path, 48000);
+        let stall = stall(&source);
+
+        // Waiting on the decoder would never come back.
+        let started = Instant::now();
+        drain(&mut source, 100_000);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        source.shared.buffered.fetch_sub(stall, Ordering::AcqRel);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn next_does_not_wait_for_a_busy_decoder() {
+        // Resampling keeps the decoder thread busy for the whole file.
+        let path = ramp_wav("busy", 5);
+        let (_audio, mut source) = open(&path, 48000);
+
+        let played = drain(&mut source, usize::MAX);
+        // Five seconds at 48 kHz stereo, give or take the resampler's edges.
+        assert!(played > 470_000, "only {played} samples came out");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// How long an output callback spends in `next` while the decoder is busy resampling and
+    /// while it is stalled. A block lasts 10.7 ms at 48 kHz, the output underruns past that.
+    #[test]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+    fn bench_next() {
+        let path = ramp_wav("bench", 30);
+
+        let (_audio, mut source) = open(&path, 48000);
+        bench("busy decoder", &mut source, 10_000);
+
+        let (_audio, mut source) = open(&path, 48000);
+        let stall = stall(&source);
+        bench("stalled decoder", &mut source, 5000);
+        source.shared.buffered.fetch_sub(stall, Ordering::AcqRel);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn short_seek_back_replays_held_audio() {
+        let path = ramp_wav("rewind", 15);
+        let (mut audio, mut source) = open(&path, RATE);
+        let at = |secs: u64| secs as usize * RATE as usize;
+
+        play_to(&mut source, at(5));
+        audio.seek(Duration::from_secs(2));
+        assert_eq!(frame_of(source.next().unwrap()), at(2) % 30000);
+        assert_eq!(audio.get_position(), Duration::from_secs(2));
+        assert_eq!(source.generation, 0, "the decoder was made to seek");
+
+        // Playing on from there crosses back into what comes off the ring.
+        play_to(&mut source, at(6));
+        assert_eq!(source.generation, 0);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn long_seek_back_goes_through_the_decoder() {
+        let path = ramp_wav("seek", 15);
+        let (mut audio, mut source) = open(&path, RATE);
+        let at = |secs: u64| secs as usize * RATE as usize;
+
+        play_to(&mut source, at(12));
+        audio.seek(Duration::from_secs(1));
+        assert_eq!(frame_of(next_sound(&mut source)), at(1) % 30000);
+        assert_eq!(source.generation, 1);
+
+        std::fs::remove_file(path).unwrap();
     }
 }
@@ -6,13 +6,30 @@ use tokio::time::sleep;
 
 pub async fn init(state: web::Data<State>) {
     tracing::info!("Watcher thread started.");
+    let deck = state.lock().await.deck.watch();
     loop {
-        sleep(Duration::from_millis(100)).await;
+        // Track switches come in as soon as the deck makes them, the tick covers the rest.
+        tokio::select! {
+            _ = sleep(Duration::from_millis(100)) => {}
+            _ = deck.changed() => {}
+        }
 
         let mut state = state.lock().await;
-        if state.sink.empty() {
-            state.next(1).await;
-            state.add().await;
+        match state.deck.poll() {
+            DeckEvent::Advanced => state.advance().await,
+            DeckEvent::Ended => {
+                if let GetReturn::Ok = state.follow().await {
+                    state.add().await;
+                }
+            }
+            DeckEvent::None if state.audio.is_none() && !state.stopped => {
+                state.next(1).await;
+                state.add().await;
+            }
+            DeckEvent::None => {}
         }
+        state.preload().await;
+        state.save_session_if_changed().await;
+        state.publish_changes();
     }
 }